@@ -0,0 +1,105 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// 传输类型，ASCII模式下行尾在网络上为CRLF，存储时为LF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferType {
+    Ascii,
+    Binary,
+}
+
+const BUF_SIZE: usize = 64 * 1024;
+
+// 下载时LF转换为CRLF，已经是CRLF的行尾不变；跳过转换后输出的前skip个字节，用于REST
+pub async fn copy_to_crlf<R, W>(reader: &mut R, writer: &mut W, mut skip: u64) -> io::Result<()>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; BUF_SIZE];
+    let mut out = Vec::with_capacity(BUF_SIZE * 2);
+    let mut prev = 0;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        out.clear();
+        for &b in &buf[..n] {
+            if b == b'\n' && prev != b'\r' {
+                out.push(b'\r');
+            }
+            out.push(b);
+            prev = b;
+        }
+        let skipped = skip.min(out.len() as u64);
+        skip -= skipped;
+        writer.write_all(&out[skipped as usize..]).await?;
+    }
+}
+
+// 上传时CRLF转换为LF，单独的CR保持不变
+pub async fn copy_from_crlf<R, W>(reader: &mut R, writer: &mut W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; BUF_SIZE];
+    let mut out = Vec::with_capacity(BUF_SIZE + 1);
+    // 上一块以CR结尾时要看下一个字节才能决定是否保留
+    let mut pending_cr = false;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        out.clear();
+        for &b in &buf[..n] {
+            if pending_cr && b != b'\n' {
+                out.push(b'\r');
+            }
+            pending_cr = b == b'\r';
+            if !pending_cr {
+                out.push(b);
+            }
+        }
+        writer.write_all(&out).await?;
+    }
+    if pending_cr {
+        writer.write_all(b"\r").await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_to_crlf() {
+        let data = b"a\nb\r\n\nc".to_vec();
+        let mut out = Vec::new();
+        copy_to_crlf(&mut &data[..], &mut out, 0).await.unwrap();
+        assert_eq!(out, b"a\r\nb\r\n\r\nc");
+
+        let mut out = Vec::new();
+        copy_to_crlf(&mut &data[..], &mut out, 2).await.unwrap();
+        assert_eq!(out, b"\nb\r\n\r\nc");
+    }
+
+    #[tokio::test]
+    async fn test_from_crlf() {
+        let mut out = Vec::new();
+        copy_from_crlf(&mut &b"a\r\nb\rc\r\n\r"[..], &mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, b"a\nb\rc\n\r");
+
+        // CR和LF分别位于两次读取中
+        let mut reader = (&b"a\r"[..]).chain(&b"\nb\r"[..]).chain(&b"c"[..]);
+        let mut out = Vec::new();
+        copy_from_crlf(&mut reader, &mut out).await.unwrap();
+        assert_eq!(out, b"a\nb\rc");
+    }
+}
@@ -0,0 +1,92 @@
+use std::io;
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use async_trait::async_trait;
+use subtle::ConstantTimeEq;
+
+use crate::config::Config;
+
+// 自定义登录校验，设置后代替配置中的用户数据库
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, username: &str, password: &str) -> io::Result<bool>;
+}
+
+// 使用配置中的用户数据库校验，未配置用户数据库时允许任意用户登录
+// 只有明确设置了anonymous的用户才能不提供密码
+pub(crate) fn check_users(config: &Config, username: &str, password: &str) -> bool {
+    if config.users_file.is_none() {
+        return true;
+    }
+    config
+        .users
+        .get(username)
+        .is_some_and(|user| match &user.password {
+            Some(expected) => verify_password(expected, password),
+            None => user.anonymous,
+        })
+}
+
+// 以$argon2开头的密码是PHC格式的Argon2哈希，其余按明文以常数时间比较
+fn verify_password(expected: &str, password: &str) -> bool {
+    if is_password_hash(expected) {
+        PasswordHash::new(expected).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    } else {
+        expected.as_bytes().ct_eq(password.as_bytes()).into()
+    }
+}
+
+pub(crate) fn is_password_hash(password: &str) -> bool {
+    password.starts_with("$argon2")
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{Algorithm, Params, PasswordHasher, Version, password_hash::SaltString};
+
+    use super::*;
+    use crate::config::UserConfig;
+
+    #[test]
+    fn test_check_users() {
+        let mut config = Config::default();
+        assert!(check_users(&config, "anyone", "anything"));
+
+        // 测试中使用较小的参数，校验时使用哈希中记录的参数
+        let params = Params::new(64, 1, 1, None).unwrap();
+        let salt = SaltString::encode_b64(b"0123456789abcdef").unwrap();
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(b"hashed", &salt)
+            .unwrap()
+            .to_string();
+        assert!(is_password_hash(&hash));
+
+        config.users_file = Some("users.toml".into());
+        let users = [
+            ("alice", Some("secret".to_string()), false),
+            ("carol", Some(hash), false),
+            ("guest", None, true),
+            ("dave", None, false),
+        ];
+        for (name, password, anonymous) in users {
+            let user = UserConfig {
+                password,
+                anonymous,
+                ..Default::default()
+            };
+            config.users.insert(name.into(), user);
+        }
+        assert!(check_users(&config, "alice", "secret"));
+        assert!(!check_users(&config, "alice", "wrong"));
+        assert!(!check_users(&config, "alice", "secret2"));
+        assert!(check_users(&config, "carol", "hashed"));
+        assert!(!check_users(&config, "carol", "wrong"));
+        assert!(check_users(&config, "guest", ""));
+        assert!(!check_users(&config, "dave", ""));
+        assert!(!check_users(&config, "bob", "secret"));
+    }
+}
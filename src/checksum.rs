@@ -0,0 +1,194 @@
+use std::{io, path::Path};
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use tokio::{io::AsyncReadExt, sync::mpsc};
+
+use crate::storage::StorageBackend;
+
+// HASH命令支持的算法，名称与FEAT中列出的一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+    Md5,
+    Crc32,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [Self; 5] = [
+        Self::Sha1,
+        Self::Sha256,
+        Self::Sha512,
+        Self::Md5,
+        Self::Crc32,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA-1",
+            Self::Sha256 => "SHA-256",
+            Self::Sha512 => "SHA-512",
+            Self::Md5 => "MD5",
+            Self::Crc32 => "CRC32",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Sha512 => Hasher::Sha512(Sha512::new()),
+            Self::Md5 => Hasher::Md5(Md5::new()),
+            Self::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+}
+
+enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Md5(Md5),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::Md5(h) => h.update(data),
+            Self::Crc32(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Self::Sha1(h) => hex::encode(h.finalize()),
+            Self::Sha256(h) => hex::encode(h.finalize()),
+            Self::Sha512(h) => hex::encode(h.finalize()),
+            Self::Md5(h) => hex::encode(h.finalize()),
+            Self::Crc32(h) => format!("{:08x}", h.finalize()),
+        }
+    }
+}
+
+const BUF_SIZE: usize = 64 * 1024;
+
+// 计算文件从start开始、到end（不含）为止的摘要，end为None表示到文件末尾
+// 读取在当前任务中进行，计算放在阻塞线程中，避免大文件占用运行时
+pub async fn checksum(
+    storage: &dyn StorageBackend,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    start: u64,
+    end: Option<u64>,
+) -> io::Result<String> {
+    let mut file = storage.open_read(path, start).await?;
+    let mut remaining = end.map(|end| end.saturating_sub(start));
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(4);
+    let worker = tokio::task::spawn_blocking(move || {
+        let mut hasher = algorithm.hasher();
+        while let Some(chunk) = rx.blocking_recv() {
+            hasher.update(&chunk);
+        }
+        hasher.finalize()
+    });
+    loop {
+        let len = remaining.map_or(BUF_SIZE, |n| n.min(BUF_SIZE as u64) as usize);
+        if len == 0 {
+            break;
+        }
+        let mut buf = vec![0; len];
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        buf.truncate(n);
+        if let Some(remaining) = remaining.as_mut() {
+            *remaining -= n as u64;
+        }
+        if tx.send(buf).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+    worker.await.map_err(io::Error::other)
+}
+
+// 解析XCRC等命令的参数：路径，可选的起始和结束位置
+// 只有加引号的路径后面可以带范围，否则整个参数都作为路径
+pub fn parse_x_args(args: &str) -> Option<(&str, u64, Option<u64>)> {
+    let Some(quoted) = args.strip_prefix('"') else {
+        return Some((args, 0, None));
+    };
+    let (path, rest) = quoted.split_once('"')?;
+    let mut numbers = rest.split_whitespace().map(str::parse::<u64>);
+    let start = numbers.next().transpose().ok()?.unwrap_or(0);
+    let end = numbers.next().transpose().ok()?;
+    if numbers.next().is_some() || end.is_some_and(|end| end < start) {
+        return None;
+    }
+    Some((path, start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::storage::MemoryFs;
+
+    #[tokio::test]
+    async fn test_checksum() {
+        let storage = MemoryFs::new();
+        let path = Path::new("/a.txt");
+        let mut file = storage.open_write(path, false).await.unwrap();
+        file.write_all(b"hello world").await.unwrap();
+        file.shutdown().await.unwrap();
+
+        let expected = [
+            "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed",
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            "309ecc489c12d6eb4cc40f50c902f2b4d0ed77ee511a7c7a9bcd3ca86d4cd86f\
+             989dd35bc5ff499670da34255b45b0cfd830e81f605dcf7dc5542e93ae9cd76f",
+            "5eb63bbbe01eeed093cb22bb8f5acdc3",
+            "0d4a1185",
+        ];
+        for (algorithm, expected) in HashAlgorithm::ALL.into_iter().zip(expected) {
+            assert_eq!(
+                checksum(&storage, path, algorithm, 0, None).await.unwrap(),
+                expected
+            );
+        }
+        assert_eq!(HashAlgorithm::parse("sha-256"), Some(HashAlgorithm::Sha256));
+        assert_eq!(HashAlgorithm::parse("SHA256"), None);
+
+        // "world"
+        assert_eq!(
+            checksum(&storage, path, HashAlgorithm::Md5, 6, Some(11))
+                .await
+                .unwrap(),
+            "7d793037a0760186574b0282f2f435e7"
+        );
+    }
+
+    #[test]
+    fn test_parse_x_args() {
+        assert_eq!(parse_x_args("a b.txt"), Some(("a b.txt", 0, None)));
+        assert_eq!(parse_x_args("\"a b.txt\" 6"), Some(("a b.txt", 6, None)));
+        assert_eq!(parse_x_args("\"a.txt\" 6 11"), Some(("a.txt", 6, Some(11))));
+        assert_eq!(parse_x_args("\"a.txt\" 6 1"), None);
+        assert_eq!(parse_x_args("\"a.txt"), None);
+    }
+}
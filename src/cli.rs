@@ -0,0 +1,166 @@
+use std::{io, net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use ftpserver::config::{Config, StorageKind, parse_port_range, parse_umask};
+
+// 命令行参数，覆盖配置文件中的同名设置（时间均以秒为单位）
+#[derive(Debug, Parser)]
+#[command(version, about = "A simple FTP server")]
+pub struct Cli {
+    /// FTP root directory
+    #[arg(value_name = "ROOT")]
+    root_dir: Option<PathBuf>,
+    /// TOML configuration file
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to listen on, can be given multiple times
+    #[arg(short, long, value_name = "ADDR")]
+    listen: Vec<SocketAddr>,
+    /// FTP root directory
+    #[arg(long, value_name = "DIR", conflicts_with = "root_dir")]
+    root: Option<PathBuf>,
+    /// Where files are stored (local, memory)
+    #[arg(long, value_name = "KIND")]
+    storage: Option<StorageKind>,
+    /// Allow browsing ZIP and TAR archives as read-only directories
+    #[arg(long)]
+    browse_archives: bool,
+    /// Keep the received part of failed uploads so they can be resumed
+    #[arg(long)]
+    keep_partial_uploads: bool,
+    /// Do not allow compressed transfers with MODE Z
+    #[arg(long)]
+    no_mode_z: bool,
+    /// Permission mask for new files and directories, in octal
+    #[arg(long, value_name = "MASK")]
+    umask: Option<String>,
+    /// Allow RNTO to replace an existing target
+    #[arg(long)]
+    rename_overwrite: bool,
+    /// Move deleted files to a recycle bin in this directory
+    #[arg(long, value_name = "DIR")]
+    trash_dir: Option<PathBuf>,
+    /// Passive port range, e.g. 50000-50100
+    #[arg(long, value_name = "START-END")]
+    pasv_ports: Option<String>,
+    /// Address or host name advertised in PASV replies
+    #[arg(long, value_name = "HOST")]
+    pasv_address: Option<String>,
+    /// Accept passive data connections from any address
+    #[arg(long)]
+    pasv_promiscuous: bool,
+    /// Greeting sent to new connections
+    #[arg(long)]
+    banner: Option<String>,
+    /// Control connection idle timeout
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
+    /// Maximum session duration
+    #[arg(long, value_name = "SECS")]
+    max_session: Option<u64>,
+    /// Data connection idle timeout
+    #[arg(long, value_name = "SECS")]
+    data_timeout: Option<u64>,
+    /// Time allowed for transfers to finish on shutdown
+    #[arg(long, value_name = "SECS")]
+    drain_timeout: Option<u64>,
+    /// Maximum number of concurrent connections
+    #[arg(long, value_name = "N")]
+    max_connections: Option<usize>,
+    /// Maximum number of concurrent connections per client address
+    #[arg(long, value_name = "N")]
+    max_connections_per_ip: Option<usize>,
+    /// Total download rate limit in bytes per second
+    #[arg(long, value_name = "BYTES")]
+    download_rate: Option<u64>,
+    /// Total upload rate limit in bytes per second
+    #[arg(long, value_name = "BYTES")]
+    upload_rate: Option<u64>,
+    /// User database file
+    #[arg(long, value_name = "FILE")]
+    users: Option<PathBuf>,
+    /// Log level (error, warn, info, debug, trace)
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<String>,
+}
+
+impl Cli {
+    // 读取配置文件并应用命令行参数
+    pub fn config(&self) -> io::Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        if !self.listen.is_empty() {
+            config.listen = self.listen.clone();
+        }
+        if let Some(root) = self.root.as_ref().or(self.root_dir.as_ref()) {
+            config.root = root.clone();
+        }
+        if let Some(storage) = self.storage {
+            config.storage = storage;
+        }
+        if self.browse_archives {
+            config.browse_archives = true;
+        }
+        if self.keep_partial_uploads {
+            config.keep_partial_uploads = true;
+        }
+        if self.no_mode_z {
+            config.mode_z = false;
+        }
+        if let Some(umask) = &self.umask {
+            config.umask = parse_umask(umask)?;
+        }
+        if self.rename_overwrite {
+            config.rename_overwrite = true;
+        }
+        if let Some(dir) = &self.trash_dir {
+            config.trash_dir = Some(dir.clone());
+        }
+        if let Some(ports) = &self.pasv_ports {
+            config.pasv_ports = Some(parse_port_range(ports)?);
+        }
+        if let Some(address) = &self.pasv_address {
+            config.pasv_address = Some(address.clone());
+        }
+        if self.pasv_promiscuous {
+            config.pasv_promiscuous = true;
+        }
+        if let Some(banner) = &self.banner {
+            config.banner = banner.clone();
+        }
+        if let Some(secs) = self.idle_timeout {
+            config.idle_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.max_session {
+            config.max_session = Some(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.data_timeout {
+            config.data_idle_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.drain_timeout {
+            config.drain_timeout = Duration::from_secs(secs);
+        }
+        if let Some(n) = self.max_connections {
+            config.max_connections = Some(n);
+        }
+        if let Some(n) = self.max_connections_per_ip {
+            config.max_connections_per_ip = Some(n);
+        }
+        if let Some(rate) = self.download_rate {
+            config.download_rate = Some(rate);
+        }
+        if let Some(rate) = self.upload_rate {
+            config.upload_rate = Some(rate);
+        }
+        if let Some(users) = &self.users {
+            config.users_file = Some(users.clone());
+        }
+        if let Some(level) = &self.log_level {
+            config.log_level = Some(level.clone());
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
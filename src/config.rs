@@ -0,0 +1,724 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    auth,
+    site::{self, SiteCommand},
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // 控制连接监听地址
+    pub listen: Vec<SocketAddr>,
+    // FTP根目录
+    pub root: PathBuf,
+    // 文件存储方式，使用内存存储时不需要根目录
+    pub storage: StorageKind,
+    // S3兼容对象存储的连接设置，storage为s3时使用
+    pub s3: Option<S3Config>,
+    // 允许将zip和tar压缩包作为只读目录浏览
+    pub browse_archives: bool,
+    // 上传中止时保留已接收的部分供续传，默认删除，只用于本地存储
+    pub keep_partial_uploads: bool,
+    // 启动时删除超过该时间没有写入的上传临时文件
+    pub stale_upload_age: Duration,
+    // 允许客户端使用MODE Z压缩传输
+    pub mode_z: bool,
+    // 新建文件和目录的权限掩码，会话中可以用SITE UMASK修改
+    pub umask: u32,
+    // RNTO允许覆盖已存在的目标，默认拒绝并回复553
+    pub rename_overwrite: bool,
+    // 回收站目录，必须位于根目录之外，DELE和RMD将文件移入其中按用户划分的子目录
+    // 未设置时直接删除，只用于本地存储
+    pub trash_dir: Option<PathBuf>,
+    // 回收站中的文件保留时间，超过后清除
+    pub trash_retention: Duration,
+    // 连接建立时发送的欢迎信息
+    pub banner: String,
+    // 日志级别，未设置时使用RUST_LOG或根据编译模式选择
+    pub log_level: Option<String>,
+    // 被动模式下不校验数据连接来源IP（用于NAT环境下客户端地址与控制连接不一致的情况）
+    pub pasv_promiscuous: bool,
+    // 被动模式等待客户端建立数据连接的超时时间
+    pub data_accept_timeout: Duration,
+    // 主动模式连接客户端数据端口的超时时间
+    pub data_connect_timeout: Duration,
+    // 传输过程中数据连接无读写的超时时间
+    pub data_idle_timeout: Duration,
+    // 被动模式端口范围，未设置时由系统分配
+    pub pasv_ports: Option<RangeInclusive<u16>>,
+    // 被动模式回复中通告的外部地址，可以是IP或主机名
+    pub pasv_address: Option<String>,
+    // 控制连接空闲超时时间，传输过程中不计入空闲
+    pub idle_timeout: Duration,
+    // 最长会话时间，未设置时不限制
+    pub max_session: Option<Duration>,
+    // 用户数据库文件，设置后登录时校验用户名和密码
+    pub users_file: Option<PathBuf>,
+    // 按用户名设置的单独配置，从用户数据库加载
+    pub users: HashMap<String, UserConfig>,
+    // 最大同时连接数，未设置时不限制
+    pub max_connections: Option<usize>,
+    // 单个IP的最大同时连接数，未设置时不限制
+    pub max_connections_per_ip: Option<usize>,
+    // 全局下载和上传速度限制，字节每秒，未设置时不限制
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+    // 单个IP的下载和上传速度限制
+    pub download_rate_per_ip: Option<u64>,
+    pub upload_rate_per_ip: Option<u64>,
+    // 服务器关闭时等待正在进行的传输完成的最长时间
+    pub drain_timeout: Duration,
+    // TLS证书和私钥文件，尚不支持TLS，设置后配置检查失败
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    #[default]
+    Local,
+    Memory,
+    S3,
+}
+
+impl FromStr for StorageKind {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "local" => Ok(Self::Local),
+            "memory" => Ok(Self::Memory),
+            "s3" => Ok(Self::S3),
+            _ => Err(invalid(format!("invalid storage '{}'", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    // 服务地址，如http://127.0.0.1:9000，使用路径方式访问bucket
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    // FTP根目录对应的对象键前缀
+    #[serde(default)]
+    pub prefix: String,
+    // 未设置时读取AWS_ACCESS_KEY_ID和AWS_SECRET_ACCESS_KEY环境变量
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+// 与配置文件中省略字段时的默认值一致
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            bucket: String::new(),
+            region: default_region(),
+            prefix: String::new(),
+            access_key: None,
+            secret_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    // 明文密码或PHC格式的Argon2哈希
+    pub password: Option<String>,
+    // 不设置密码，接受任意密码
+    #[serde(default)]
+    pub anonymous: bool,
+    // 覆盖全局的最长会话时间
+    #[serde(default, with = "secs")]
+    pub max_session: Option<Duration>,
+    // 该用户所有会话共享的下载和上传速度限制，字节每秒
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+    // 用户的主目录，登录后只能访问该目录，客户端看到的"/"对应主目录
+    pub home: Option<PathBuf>,
+    // 主目录下可使用的总空间和文件数，设置配额的用户必须有单独的主目录
+    pub quota: Option<Quota>,
+    // 按目录设置的配额，目录为客户端看到的路径
+    #[serde(default)]
+    pub dir_quotas: BTreeMap<PathBuf, Quota>,
+    // 允许使用的SITE子命令，HELP总是允许
+    // 未设置时不允许CHMOD、UTIME、CPFR和CPTO，UTIME同时控制MFMT和MFF
+    pub site_commands: Option<Vec<String>>,
+}
+
+// 空间和文件数限制，未设置的项不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    pub bytes: Option<u64>,
+    pub files: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], 2121))],
+            #[cfg(not(windows))]
+            root: PathBuf::from("/var/ftp"),
+            #[cfg(windows)]
+            root: PathBuf::from("C:\\ftp"),
+            storage: StorageKind::Local,
+            s3: None,
+            browse_archives: false,
+            keep_partial_uploads: false,
+            stale_upload_age: Duration::from_secs(3600),
+            mode_z: true,
+            umask: 0o022,
+            rename_overwrite: false,
+            trash_dir: None,
+            trash_retention: Duration::from_secs(30 * 86400),
+            banner: "Service ready for new user".to_string(),
+            log_level: None,
+            pasv_promiscuous: false,
+            data_accept_timeout: Duration::from_secs(30),
+            data_connect_timeout: Duration::from_secs(30),
+            data_idle_timeout: Duration::from_secs(300),
+            pasv_ports: None,
+            pasv_address: None,
+            idle_timeout: Duration::from_secs(600),
+            max_session: None,
+            users_file: None,
+            users: HashMap::new(),
+            max_connections: None,
+            max_connections_per_ip: None,
+            download_rate: None,
+            upload_rate: None,
+            download_rate_per_ip: None,
+            upload_rate_per_ip: None,
+            drain_timeout: Duration::from_secs(30),
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+// 配置文件格式，所有字段均可省略
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    listen: Option<Vec<SocketAddr>>,
+    root: Option<PathBuf>,
+    storage: Option<StorageKind>,
+    s3: Option<S3Config>,
+    browse_archives: Option<bool>,
+    keep_partial_uploads: Option<bool>,
+    mode_z: Option<bool>,
+    // 八进制字符串，如"022"
+    umask: Option<String>,
+    rename_overwrite: Option<bool>,
+    banner: Option<String>,
+    log_level: Option<String>,
+    users_file: Option<PathBuf>,
+    passive: PassiveSection,
+    timeouts: TimeoutSection,
+    limits: LimitSection,
+    tls: TlsSection,
+    trash: TrashSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PassiveSection {
+    ports: Option<String>,
+    address: Option<String>,
+    promiscuous: Option<bool>,
+}
+
+// 时间均以秒为单位
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TimeoutSection {
+    idle: Option<u64>,
+    max_session: Option<u64>,
+    data_accept: Option<u64>,
+    data_connect: Option<u64>,
+    data_idle: Option<u64>,
+    drain: Option<u64>,
+    stale_upload: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitSection {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    download_rate: Option<u64>,
+    upload_rate: Option<u64>,
+    download_rate_per_ip: Option<u64>,
+    upload_rate_per_ip: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TrashSection {
+    dir: Option<PathBuf>,
+    retention_days: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UsersFile {
+    users: HashMap<String, UserConfig>,
+}
+
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(Duration::from_secs))
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("cannot read config file {}: {}", path.display(), e)))?;
+        Self::from_toml(&text)
+            .map_err(|e| invalid(format!("config file {}: {}", path.display(), e)))
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let file: FileConfig = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        let mut config = Self::default();
+        if let Some(listen) = file.listen {
+            config.listen = listen;
+        }
+        if let Some(root) = file.root {
+            config.root = root;
+        }
+        if let Some(storage) = file.storage {
+            config.storage = storage;
+        }
+        config.s3 = file.s3;
+        if let Some(browse) = file.browse_archives {
+            config.browse_archives = browse;
+        }
+        if let Some(keep) = file.keep_partial_uploads {
+            config.keep_partial_uploads = keep;
+        }
+        if let Some(mode_z) = file.mode_z {
+            config.mode_z = mode_z;
+        }
+        if let Some(umask) = file.umask {
+            config.umask = parse_umask(&umask)?;
+        }
+        if let Some(overwrite) = file.rename_overwrite {
+            config.rename_overwrite = overwrite;
+        }
+        if let Some(banner) = file.banner {
+            config.banner = banner;
+        }
+        config.log_level = file.log_level;
+        config.users_file = file.users_file;
+
+        if let Some(ports) = file.passive.ports {
+            config.pasv_ports = Some(parse_port_range(&ports)?);
+        }
+        config.pasv_address = file.passive.address;
+        if let Some(promiscuous) = file.passive.promiscuous {
+            config.pasv_promiscuous = promiscuous;
+        }
+
+        let t = file.timeouts;
+        let set = |field: &mut Duration, secs: Option<u64>| {
+            if let Some(secs) = secs {
+                *field = Duration::from_secs(secs);
+            }
+        };
+        set(&mut config.idle_timeout, t.idle);
+        set(&mut config.data_accept_timeout, t.data_accept);
+        set(&mut config.data_connect_timeout, t.data_connect);
+        set(&mut config.data_idle_timeout, t.data_idle);
+        set(&mut config.drain_timeout, t.drain);
+        set(&mut config.stale_upload_age, t.stale_upload);
+        config.max_session = t.max_session.map(Duration::from_secs);
+
+        config.max_connections = file.limits.max_connections;
+        config.max_connections_per_ip = file.limits.max_connections_per_ip;
+        config.download_rate = file.limits.download_rate;
+        config.upload_rate = file.limits.upload_rate;
+        config.download_rate_per_ip = file.limits.download_rate_per_ip;
+        config.upload_rate_per_ip = file.limits.upload_rate_per_ip;
+        config.tls_cert = file.tls.cert;
+        config.tls_key = file.tls.key;
+        config.trash_dir = file.trash.dir;
+        if let Some(days) = file.trash.retention_days {
+            config.trash_retention = Duration::from_secs(days * 86400);
+        }
+        Ok(config)
+    }
+
+    // 检查配置是否有效，规范化根目录并加载用户数据库
+    pub fn validate(&mut self) -> io::Result<()> {
+        if self.storage == StorageKind::Local {
+            self.root = dunce::canonicalize(&self.root)
+                .map_err(|e| invalid(format!("root {}: {}", self.root.display(), e)))?;
+            if !self.root.is_dir() {
+                return Err(invalid(format!(
+                    "root {} is not a directory",
+                    self.root.display()
+                )));
+            }
+            if let Some(dir) = &self.trash_dir {
+                let err = |e: io::Error| invalid(format!("trash {}: {}", dir.display(), e));
+                std::fs::create_dir_all(dir).map_err(err)?;
+                let dir = dunce::canonicalize(dir).map_err(err)?;
+                if dir.starts_with(&self.root) || self.root.starts_with(&dir) {
+                    return Err(invalid(format!(
+                        "trash {} must be outside of the root directory",
+                        dir.display()
+                    )));
+                }
+                self.trash_dir = Some(dir);
+            }
+        } else if self.trash_dir.is_some() {
+            return Err(invalid("trash requires local storage".into()));
+        }
+        self.validate_settings()
+    }
+
+    // 检查根目录以外的设置，使用自定义存储后端时不需要根目录
+    pub(crate) fn validate_settings(&mut self) -> io::Result<()> {
+        if self.listen.is_empty() {
+            return Err(invalid("at least one listen address is required".into()));
+        }
+        if self.storage == StorageKind::S3 {
+            if cfg!(not(feature = "s3")) {
+                return Err(invalid("built without S3 support".into()));
+            }
+            if self.s3.is_none() {
+                return Err(invalid("storage s3 requires an [s3] section".into()));
+            }
+        }
+        if let Some(level) = &self.log_level {
+            log::LevelFilter::from_str(level)
+                .map_err(|_| invalid(format!("invalid log level '{}'", level)))?;
+        }
+        if let Some(ports) = &self.pasv_ports
+            && (ports.is_empty() || *ports.start() == 0)
+        {
+            return Err(invalid(format!(
+                "invalid passive port range {}-{}",
+                ports.start(),
+                ports.end()
+            )));
+        }
+        for (name, timeout) in [
+            ("idle", self.idle_timeout),
+            ("data_accept", self.data_accept_timeout),
+            ("data_connect", self.data_connect_timeout),
+            ("data_idle", self.data_idle_timeout),
+        ] {
+            if timeout.is_zero() {
+                return Err(invalid(format!("{} timeout must be greater than 0", name)));
+            }
+        }
+        if self.trash_retention.is_zero() {
+            return Err(invalid("trash retention must be greater than 0".into()));
+        }
+        if self.max_connections == Some(0) || self.max_connections_per_ip == Some(0) {
+            return Err(invalid("connection limits must be greater than 0".into()));
+        }
+        let rates = [
+            self.download_rate,
+            self.upload_rate,
+            self.download_rate_per_ip,
+            self.upload_rate_per_ip,
+        ];
+        if rates.contains(&Some(0)) {
+            return Err(invalid("rate limits must be greater than 0".into()));
+        }
+        // 不能接受TLS设置后以明文提供服务
+        if self.tls_cert.is_some() || self.tls_key.is_some() {
+            return Err(invalid(
+                "TLS is not supported, remove the [tls] cert and key settings".into(),
+            ));
+        }
+        if let Some(path) = &self.users_file {
+            self.users = load_users(path)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn load_users(path: &Path) -> io::Result<HashMap<String, UserConfig>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| invalid(format!("cannot read users file {}: {}", path.display(), e)))?;
+    let file: UsersFile = toml::from_str(&text)
+        .map_err(|e| invalid(format!("users file {}: {}", path.display(), e)))?;
+    if file.users.keys().any(|name| name.is_empty()) {
+        return Err(invalid(format!(
+            "users file {}: empty user name",
+            path.display()
+        )));
+    }
+    for (name, user) in &file.users {
+        match (&user.password, user.anonymous) {
+            (None, false) => {
+                return Err(invalid(format!(
+                    "users file {}: user {} has no password, set a password or anonymous = true",
+                    path.display(),
+                    name
+                )));
+            }
+            (Some(_), true) => {
+                return Err(invalid(format!(
+                    "users file {}: user {} cannot have both a password and anonymous = true",
+                    path.display(),
+                    name
+                )));
+            }
+            (Some(password), false) if auth::is_password_hash(password) => {
+                if let Err(e) = argon2::PasswordHash::new(password) {
+                    return Err(invalid(format!(
+                        "users file {}: invalid password hash for user {}: {}",
+                        path.display(),
+                        name,
+                        e
+                    )));
+                }
+            }
+            _ => {}
+        }
+        if let Some(home) = &user.home
+            && (!home.starts_with("/") || home.components().any(|c| c == Component::ParentDir))
+        {
+            return Err(invalid(format!(
+                "users file {}: home directory {} of user {} must be an absolute path without ..",
+                path.display(),
+                home.display(),
+                name
+            )));
+        }
+        if (user.quota.is_some() || !user.dir_quotas.is_empty())
+            && user.home.as_ref().is_none_or(|home| home == Path::new("/"))
+        {
+            return Err(invalid(format!(
+                "users file {}: user {} has a quota but no home directory of their own",
+                path.display(),
+                name
+            )));
+        }
+        if let Some(dir) = user.dir_quotas.keys().find(|dir| !dir.starts_with("/")) {
+            return Err(invalid(format!(
+                "users file {}: quota directory {} of user {} must be absolute",
+                path.display(),
+                dir.display(),
+                name
+            )));
+        }
+        if [user.download_rate, user.upload_rate].contains(&Some(0)) {
+            return Err(invalid(format!(
+                "users file {}: rate limits of user {} must be greater than 0",
+                path.display(),
+                name
+            )));
+        }
+        if let Some(command) = user
+            .site_commands
+            .iter()
+            .flatten()
+            .find(|command| SiteCommand::parse(command).is_none())
+        {
+            return Err(invalid(format!(
+                "users file {}: unknown SITE command {} for user {}",
+                path.display(),
+                command,
+                name
+            )));
+        }
+    }
+    // 配额按主目录统计，不同用户的主目录重叠时会互相计入
+    let homes: Vec<_> = file
+        .users
+        .iter()
+        .filter(|(_, user)| user.quota.is_some() || !user.dir_quotas.is_empty())
+        .filter_map(|(name, user)| Some((name, user.home.as_ref()?)))
+        .collect();
+    for (i, (name, home)) in homes.iter().enumerate() {
+        if let Some((other, _)) = homes[i + 1..]
+            .iter()
+            .find(|(_, other)| home.starts_with(other) || other.starts_with(home))
+        {
+            return Err(invalid(format!(
+                "users file {}: users {} and {} have quotas on overlapping home directories",
+                path.display(),
+                name,
+                other
+            )));
+        }
+    }
+    Ok(file.users)
+}
+
+pub fn parse_umask(s: &str) -> io::Result<u32> {
+    site::parse_mode(s)
+        .filter(|&mask| mask <= 0o777)
+        .ok_or_else(|| invalid(format!("invalid umask '{}'", s)))
+}
+
+// 解析形如"50000-50100"的端口范围
+pub fn parse_port_range(s: &str) -> io::Result<RangeInclusive<u16>> {
+    let err = || invalid(format!("invalid port range '{}', expected START-END", s));
+    let (start, end) = s.split_once('-').ok_or_else(err)?;
+    let start: u16 = start.trim().parse().map_err(|_| err())?;
+    let end: u16 = end.trim().parse().map_err(|_| err())?;
+    if start == 0 || start > end {
+        return Err(err());
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            listen = ["127.0.0.1:21", "[::1]:21"]
+            root = "/srv/ftp"
+            banner = "hello"
+            umask = "027"
+
+            [passive]
+            ports = "50000-50100"
+            address = "ftp.example.com"
+
+            [timeouts]
+            idle = 60
+            max_session = 3600
+
+            [limits]
+            max_connections_per_ip = 4
+
+            [s3]
+            endpoint = "http://127.0.0.1:9000"
+            bucket = "firmware"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.root, PathBuf::from("/srv/ftp"));
+        assert_eq!(config.banner, "hello");
+        assert_eq!(config.umask, 0o027);
+        assert_eq!(config.pasv_ports, Some(50000..=50100));
+        assert_eq!(config.pasv_address.as_deref(), Some("ftp.example.com"));
+        assert_eq!(config.idle_timeout, Duration::from_secs(60));
+        assert_eq!(config.max_session, Some(Duration::from_secs(3600)));
+        assert_eq!(config.max_connections, None);
+        assert_eq!(config.max_connections_per_ip, Some(4));
+        let s3 = config.s3.unwrap();
+        assert_eq!(s3.bucket, "firmware");
+        assert_eq!(s3.region, "us-east-1");
+        assert_eq!(S3Config::default().region, s3.region);
+        // 未设置的字段保持默认值
+        assert_eq!(config.drain_timeout, Duration::from_secs(30));
+        assert_eq!(config.storage, StorageKind::Local);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(Config::from_toml("unknown = 1").is_err());
+        assert!(Config::from_toml("[passive]\nports = \"100-50\"").is_err());
+        assert!(Config::from_toml("umask = \"1022\"").is_err());
+        assert!(parse_port_range("50000").is_err());
+        assert!(parse_port_range("0-10").is_err());
+        assert_eq!(parse_port_range("1-2").unwrap(), 1..=2);
+
+        let mut config = Config {
+            root: std::env::temp_dir(),
+            idle_timeout: Duration::ZERO,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        config.idle_timeout = Duration::from_secs(1);
+        config.validate().unwrap();
+        config.log_level = Some("loud".into());
+        assert!(config.validate().is_err());
+        config.log_level = None;
+        config.tls_cert = Some(std::env::temp_dir());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("TLS is not supported"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_users() {
+        let path =
+            std::env::temp_dir().join(format!("ftpserver-users-{}.toml", std::process::id()));
+        let load = |text: &str| {
+            std::fs::write(&path, text).unwrap();
+            load_users(&path)
+        };
+        let users =
+            load("[users.alice]\npassword = \"x\"\n[users.guest]\nanonymous = true\n").unwrap();
+        assert_eq!(users["alice"].password.as_deref(), Some("x"));
+        assert!(users["guest"].anonymous);
+
+        // 缺少密码必须明确设置anonymous
+        let err = load("[users.alice]\n").unwrap_err();
+        assert!(err.to_string().contains("has no password"), "{}", err);
+        assert!(load("[users.alice]\npassword = \"x\"\nanonymous = true\n").is_err());
+        assert!(
+            load("[users.alice]\npassword = \"$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHQ$!!\"\n")
+                .is_err()
+        );
+
+        // 配额只能设置在各用户不重叠的主目录上
+        let quota = "password = \"x\"\nquota = { bytes = 1 }\n";
+        let err = load(&format!("[users.alice]\n{}", quota)).unwrap_err();
+        assert!(err.to_string().contains("no home directory"), "{}", err);
+        let err = load(&format!(
+            "[users.alice]\n{}home = \"/a\"\n[users.bob]\n{}home = \"/a/b\"\n",
+            quota, quota
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("overlapping"), "{}", err);
+        assert!(load(&format!("[users.alice]\n{}home = \"/a/../b\"\n", quota)).is_err());
+        load(&format!(
+            "[users.alice]\n{}home = \"/a\"\n[users.bob]\n{}home = \"/ab\"\n",
+            quota, quota
+        ))
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+// 统计当前连接数，限制总连接数和单个IP的连接数
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    max_total: Option<usize>,
+    max_per_ip: Option<usize>,
+    counts: Arc<Mutex<Counts>>,
+}
+
+// 连接结束时释放计数
+pub struct ConnectionGuard {
+    ip: IpAddr,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_total: Option<usize>, max_per_ip: Option<usize>) -> Self {
+        Self {
+            max_total,
+            max_per_ip,
+            counts: Arc::default(),
+        }
+    }
+
+    // 重新加载配置时更新限制，已有连接不受影响
+    pub fn set_limits(&mut self, max_total: Option<usize>, max_per_ip: Option<usize>) {
+        self.max_total = max_total;
+        self.max_per_ip = max_per_ip;
+    }
+
+    // 超过限制时返回None
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let ip = ip.to_canonical();
+        let mut counts = self.counts.lock().unwrap();
+        let from_ip = counts.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.max_total.is_some_and(|max| counts.total >= max)
+            || self.max_per_ip.is_some_and(|max| from_ip >= max)
+        {
+            return None;
+        }
+        counts.total += 1;
+        counts.per_ip.insert(ip, from_ip + 1);
+        Some(ConnectionGuard {
+            ip,
+            counts: self.counts.clone(),
+        })
+    }
+
+    // 返回(总连接数, 该IP的连接数)
+    pub fn count(&self, ip: IpAddr) -> (usize, usize) {
+        let counts = self.counts.lock().unwrap();
+        let from_ip = counts.per_ip.get(&ip.to_canonical()).copied();
+        (counts.total, from_ip.unwrap_or(0))
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(n) = counts.per_ip.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+        log::info!(
+            "Connection from {} released, {} active connections",
+            self.ip,
+            counts.total
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let limiter = ConnectionLimiter::new(Some(3), Some(2));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let a1 = limiter.acquire(a).unwrap();
+        let _a2 = limiter.acquire(a).unwrap();
+        assert!(limiter.acquire(a).is_none());
+        let _b1 = limiter.acquire(b).unwrap();
+        // 总连接数已满
+        assert!(limiter.acquire(b).is_none());
+        assert_eq!(limiter.count(a), (3, 2));
+        drop(a1);
+        assert_eq!(limiter.count(a), (2, 1));
+        assert!(limiter.acquire(b).is_some());
+    }
+}
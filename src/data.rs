@@ -0,0 +1,165 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::{Instant, Sleep},
+};
+
+use crate::throttle::Rates;
+
+// 数据连接，读写在超过空闲时间没有进展时返回超时错误
+// 读取计入上传限速，写入计入下载限速
+pub struct DataStream {
+    inner: TcpStream,
+    idle_timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+    rates: Vec<Arc<Rates>>,
+    // 超出限速后下一次读写前需要等待
+    throttled: Option<Pin<Box<Sleep>>>,
+}
+
+impl DataStream {
+    pub fn new(inner: TcpStream, idle_timeout: Duration) -> Self {
+        Self {
+            inner,
+            idle_timeout,
+            deadline: Box::pin(tokio::time::sleep(idle_timeout)),
+            rates: Vec::new(),
+            throttled: None,
+        }
+    }
+
+    pub fn throttle(mut self, rates: Vec<Arc<Rates>>) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    fn poll_throttled(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(sleep) = &mut self.throttled {
+            ready!(sleep.as_mut().poll(cx));
+            self.throttled = None;
+            // 等待限速的时间不计入空闲
+            self.reset();
+        }
+        Poll::Ready(())
+    }
+
+    fn charge(&mut self, n: usize, upload: bool) {
+        let wait = self
+            .rates
+            .iter()
+            .map(|rates| match upload {
+                true => rates.upload.consume(n),
+                false => rates.download.consume(n),
+            })
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            self.throttled = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+    }
+
+    fn reset(&mut self) {
+        let deadline = Instant::now() + self.idle_timeout;
+        self.deadline.as_mut().reset(deadline);
+    }
+
+    fn poll_idle<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match poll {
+            Poll::Ready(res) => {
+                self.reset();
+                Poll::Ready(res)
+            }
+            Poll::Pending => match self.deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Data connection idle timeout",
+                ))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl AsyncRead for DataStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_throttled(cx));
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.charge(buf.filled().len() - filled, true);
+        }
+        this.poll_idle(cx, poll)
+    }
+}
+
+impl AsyncWrite for DataStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_throttled(cx));
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            this.charge(n, false);
+        }
+        this.poll_idle(cx, poll)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        this.poll_idle(cx, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.poll_idle(cx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let mut stream = DataStream::new(server, Duration::from_millis(100));
+
+        client.write_all(b"abc").await.unwrap();
+        let mut buf = [0; 3];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"abc");
+
+        // 对端不再发送数据
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
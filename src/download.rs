@@ -0,0 +1,551 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use async_compression::tokio::write::GzipEncoder;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    listing,
+    storage::{FileKind, Metadata, ReadStream, StorageBackend},
+};
+
+// 目录打包下载支持的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    // 按扩展名拆分出要打包的目录，如/a/dir.tar.gz -> (/a/dir, TarGz)
+    pub fn split(path: &Path) -> Option<(PathBuf, Self)> {
+        let name = path.file_name()?.to_string_lossy();
+        let lower = name.to_lowercase();
+        let (len, format) = [
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar", Self::Tar),
+            (".zip", Self::Zip),
+        ]
+        .into_iter()
+        .find(|(ext, _)| lower.ends_with(ext) && lower.len() > ext.len())
+        .map(|(ext, format)| (name.len() - ext.len(), format))?;
+        Some((path.with_file_name(&name[..len]), format))
+    }
+}
+
+struct Item {
+    path: PathBuf,
+    // 包内名称，目录以"/"结尾
+    name: String,
+    metadata: Metadata,
+}
+
+// 与LIST看到的内容相同，不跟随符号链接
+async fn collect(storage: &dyn StorageBackend, dir: &Path) -> io::Result<Vec<Item>> {
+    let base = dir.file_name().map_or("root".to_string(), |name| {
+        name.to_string_lossy().into_owned()
+    });
+    let mut items = Vec::new();
+    let mut stack = vec![(dir.to_path_buf(), base)];
+    while let Some((path, name)) = stack.pop() {
+        let metadata = storage.metadata(&path).await?;
+        items.push(Item {
+            path: path.clone(),
+            name: format!("{}/", name),
+            metadata,
+        });
+        for entry in storage.list(&path).await? {
+            let child = path.join(&entry.name);
+            let child_name = format!("{}/{}", name, entry.name);
+            match entry.metadata.kind {
+                FileKind::Dir => stack.push((child, child_name)),
+                FileKind::File => items.push(Item {
+                    path: child,
+                    name: child_name,
+                    metadata: entry.metadata,
+                }),
+                FileKind::Symlink => {}
+            }
+        }
+    }
+    // 目录在其内容之前
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(items)
+}
+
+// 打开失败的文件跳过，不中断整个下载
+async fn open(storage: &dyn StorageBackend, item: &Item) -> Option<ReadStream> {
+    match storage.open_read(&item.path, 0).await {
+        Ok(reader) => Some(reader),
+        Err(e) => {
+            log::warn!("Skipping {} in archive: {}", item.path.display(), e);
+            None
+        }
+    }
+}
+
+// 复制文件内容，大小必须与目录中记录的一致
+async fn copy<W: AsyncWrite + Unpin>(
+    reader: &mut ReadStream,
+    out: &mut W,
+    expected: u64,
+    mut crc: Option<&mut crc32fast::Hasher>,
+) -> io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut total = 0;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        total += n as u64;
+        if total > expected {
+            break;
+        }
+        if let Some(crc) = crc.as_mut() {
+            crc.update(&buf[..n]);
+        }
+        out.write_all(&buf[..n]).await?;
+    }
+    if total != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File changed while it was being archived",
+        ));
+    }
+    Ok(())
+}
+
+// 打包目录并写入out，不在磁盘上生成临时文件
+pub async fn write_archive<W: AsyncWrite + Unpin + Send>(
+    storage: &dyn StorageBackend,
+    dir: &Path,
+    format: ArchiveFormat,
+    mut out: W,
+) -> io::Result<()> {
+    let items = collect(storage, dir).await?;
+    match format {
+        ArchiveFormat::Tar => {
+            write_tar(storage, &items, &mut out).await?;
+            out.shutdown().await
+        }
+        ArchiveFormat::TarGz => {
+            let mut gz = GzipEncoder::new(out);
+            write_tar(storage, &items, &mut gz).await?;
+            gz.shutdown().await
+        }
+        ArchiveFormat::Zip => {
+            write_zip(storage, &items, &mut out, ZIP64_LIMIT).await?;
+            out.shutdown().await
+        }
+    }
+}
+
+fn tar_header(name: &str, metadata: &Metadata) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    let (kind, mode, size) = if metadata.is_dir() {
+        (tar::EntryType::Directory, 0o755, 0)
+    } else {
+        (tar::EntryType::Regular, 0o644, metadata.len)
+    };
+    header.set_entry_type(kind);
+    header.set_mode(metadata.mode.unwrap_or(mode));
+    header.set_size(size);
+    let mtime = metadata
+        .modified
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    header.set_mtime(mtime);
+    // 超过100字节的名称由前面的GNU长名称条目给出，这里只保留截断的部分
+    let bytes = name.as_bytes();
+    let field = &mut header.as_old_mut().name;
+    let len = bytes.len().min(field.len());
+    field[..len].copy_from_slice(&bytes[..len]);
+    header.set_cksum();
+    header
+}
+
+async fn write_padding<W: AsyncWrite + Unpin>(out: &mut W, len: u64) -> io::Result<()> {
+    let padding = (512 - len % 512) % 512;
+    out.write_all(&[0; 512][..padding as usize]).await
+}
+
+async fn write_tar<W: AsyncWrite + Unpin>(
+    storage: &dyn StorageBackend,
+    items: &[Item],
+    out: &mut W,
+) -> io::Result<()> {
+    for item in items {
+        let mut reader = None;
+        if item.metadata.is_file() {
+            reader = open(storage, item).await;
+            if reader.is_none() {
+                continue;
+            }
+        }
+        if item.name.len() > 100 {
+            let mut long = tar::Header::new_gnu();
+            long.set_entry_type(tar::EntryType::GNULongName);
+            long.as_old_mut().name[..13].copy_from_slice(b"././@LongLink");
+            long.set_mode(0o644);
+            long.set_size(item.name.len() as u64 + 1);
+            long.set_cksum();
+            out.write_all(long.as_bytes()).await?;
+            out.write_all(item.name.as_bytes()).await?;
+            out.write_all(&[0]).await?;
+            write_padding(out, item.name.len() as u64 + 1).await?;
+        }
+        out.write_all(tar_header(&item.name, &item.metadata).as_bytes())
+            .await?;
+        if let Some(mut reader) = reader {
+            copy(&mut reader, out, item.metadata.len, None).await?;
+            write_padding(out, item.metadata.len).await?;
+        }
+    }
+    out.write_all(&[0; 1024]).await
+}
+
+// ZIP中超过该值的大小和偏移需要使用ZIP64扩展
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+
+struct CentralEntry {
+    name: String,
+    dir: bool,
+    mode: u32,
+    time: u16,
+    date: u16,
+    crc: u32,
+    size: u64,
+    offset: u64,
+}
+
+fn dos_time(metadata: &Metadata) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) =
+        listing::civil_time(metadata.modified.unwrap_or(UNIX_EPOCH));
+    // DOS时间从1980年开始
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (hour << 11) | (minute << 5) | (second / 2);
+    let date = (((year - 1980).min(127) as u32) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+// 写入时记录偏移量，用于生成中央目录
+struct Counter<'a, W> {
+    out: &'a mut W,
+    offset: u64,
+}
+
+impl<W: AsyncWrite + Unpin> Counter<'_, W> {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data).await?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+}
+
+// 只存储不压缩，文件内容之后用数据描述符给出CRC和大小，因此不需要预先读取文件
+// 大小或偏移不小于zip64_limit时使用ZIP64扩展，测试中降低该值以覆盖ZIP64的情况
+async fn write_zip<W: AsyncWrite + Unpin>(
+    storage: &dyn StorageBackend,
+    items: &[Item],
+    out: &mut W,
+    zip64_limit: u64,
+) -> io::Result<()> {
+    // 使用ZIP64扩展的字段在原位置写入0xFFFFFFFF
+    let clamp = |value: u64| match value >= zip64_limit {
+        true => u32::MAX,
+        false => value as u32,
+    };
+    let mut out = Counter { out, offset: 0 };
+    let mut entries = Vec::new();
+    for item in items {
+        let dir = item.metadata.is_dir();
+        let mut reader = None;
+        if !dir {
+            reader = open(storage, item).await;
+            if reader.is_none() {
+                continue;
+            }
+        }
+        let size = if dir { 0 } else { item.metadata.len };
+        let zip64 = size >= zip64_limit;
+        let (time, date) = dos_time(&item.metadata);
+        let offset = out.offset;
+
+        let mut header = Vec::with_capacity(30 + item.name.len() + 20);
+        header.extend(0x04034b50u32.to_le_bytes());
+        header.extend((if zip64 { 45u16 } else { 20 }).to_le_bytes());
+        // 使用数据描述符，文件名为UTF-8
+        header.extend(0x0808u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(time.to_le_bytes());
+        header.extend(date.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        let placeholder = if zip64 { u32::MAX } else { 0 };
+        header.extend(placeholder.to_le_bytes());
+        header.extend(placeholder.to_le_bytes());
+        header.extend((item.name.len() as u16).to_le_bytes());
+        header.extend((if zip64 { 20u16 } else { 0 }).to_le_bytes());
+        header.extend(item.name.as_bytes());
+        if zip64 {
+            header.extend(1u16.to_le_bytes());
+            header.extend(16u16.to_le_bytes());
+            header.extend([0; 16]);
+        }
+        out.write(&header).await?;
+
+        // 文件大小与收集时不一致时copy返回错误，整个下载失败，不会生成内容错误的条目
+        let mut crc = crc32fast::Hasher::new();
+        if let Some(mut reader) = reader {
+            copy(&mut reader, out.out, size, Some(&mut crc)).await?;
+            out.offset += size;
+        }
+        let crc = crc.finalize();
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.extend(0x08074b50u32.to_le_bytes());
+        descriptor.extend(crc.to_le_bytes());
+        if zip64 {
+            descriptor.extend(size.to_le_bytes());
+            descriptor.extend(size.to_le_bytes());
+        } else {
+            descriptor.extend((size as u32).to_le_bytes());
+            descriptor.extend((size as u32).to_le_bytes());
+        }
+        out.write(&descriptor).await?;
+
+        entries.push(CentralEntry {
+            name: item.name.clone(),
+            dir,
+            mode: item
+                .metadata
+                .mode
+                .unwrap_or(if dir { 0o755 } else { 0o644 }),
+            time,
+            date,
+            crc,
+            size,
+            offset,
+        });
+    }
+
+    let cd_offset = out.offset;
+    for entry in &entries {
+        let mut extra = Vec::new();
+        if entry.size >= zip64_limit {
+            extra.extend(entry.size.to_le_bytes());
+            extra.extend(entry.size.to_le_bytes());
+        }
+        if entry.offset >= zip64_limit {
+            extra.extend(entry.offset.to_le_bytes());
+        }
+        let zip64 = !extra.is_empty();
+
+        let mut header = Vec::with_capacity(46 + entry.name.len() + 28);
+        header.extend(0x02014b50u32.to_le_bytes());
+        // 由Unix系统创建，外部属性中保存权限
+        header.extend((0x0300u16 | 45).to_le_bytes());
+        header.extend((if zip64 { 45u16 } else { 20 }).to_le_bytes());
+        header.extend(0x0808u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(entry.time.to_le_bytes());
+        header.extend(entry.date.to_le_bytes());
+        header.extend(entry.crc.to_le_bytes());
+        header.extend(clamp(entry.size).to_le_bytes());
+        header.extend(clamp(entry.size).to_le_bytes());
+        header.extend((entry.name.len() as u16).to_le_bytes());
+        header.extend((if zip64 { extra.len() as u16 + 4 } else { 0 }).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        let kind = if entry.dir { 0o040000 } else { 0o100000 };
+        let attributes = ((kind | entry.mode) << 16) | if entry.dir { 0x10 } else { 0 };
+        header.extend(attributes.to_le_bytes());
+        header.extend(clamp(entry.offset).to_le_bytes());
+        header.extend(entry.name.as_bytes());
+        if zip64 {
+            header.extend(1u16.to_le_bytes());
+            header.extend((extra.len() as u16).to_le_bytes());
+            header.extend(extra);
+        }
+        out.write(&header).await?;
+    }
+    let cd_size = out.offset - cd_offset;
+    let count = entries.len() as u64;
+
+    let mut end = Vec::new();
+    if count >= 0xFFFF || cd_offset >= zip64_limit || cd_size >= zip64_limit {
+        let zip64_end = out.offset;
+        end.extend(0x06064b50u32.to_le_bytes());
+        end.extend(44u64.to_le_bytes());
+        end.extend(45u16.to_le_bytes());
+        end.extend(45u16.to_le_bytes());
+        end.extend(0u32.to_le_bytes());
+        end.extend(0u32.to_le_bytes());
+        end.extend(count.to_le_bytes());
+        end.extend(count.to_le_bytes());
+        end.extend(cd_size.to_le_bytes());
+        end.extend(cd_offset.to_le_bytes());
+        end.extend(0x07064b50u32.to_le_bytes());
+        end.extend(0u32.to_le_bytes());
+        end.extend(zip64_end.to_le_bytes());
+        end.extend(1u32.to_le_bytes());
+    }
+    end.extend(0x06054b50u32.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend((count.min(0xFFFF) as u16).to_le_bytes());
+    end.extend((count.min(0xFFFF) as u16).to_le_bytes());
+    end.extend(clamp(cd_size).to_le_bytes());
+    end.extend(clamp(cd_offset).to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    out.write(&end).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+    use crate::storage::MemoryFs;
+
+    async fn put(fs: &MemoryFs, path: &str, data: &[u8]) {
+        let mut w = fs.open_write(Path::new(path), false).await.unwrap();
+        w.write_all(data).await.unwrap();
+        w.shutdown().await.unwrap();
+    }
+
+    async fn tree() -> MemoryFs {
+        let fs = MemoryFs::new();
+        fs.mkdir(Path::new("/dir")).await.unwrap();
+        fs.mkdir(Path::new("/dir/sub")).await.unwrap();
+        put(&fs, "/dir/a.txt", b"hello").await;
+        let long = format!("/dir/sub/{}.txt", "x".repeat(120));
+        put(&fs, &long, b"long name").await;
+        fs
+    }
+
+    async fn archive(fs: &MemoryFs, format: ArchiveFormat) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_archive(fs, Path::new("/dir"), format, &mut out)
+            .await
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            ArchiveFormat::split(Path::new("/a/dir.tar.gz")),
+            Some((PathBuf::from("/a/dir"), ArchiveFormat::TarGz))
+        );
+        assert_eq!(
+            ArchiveFormat::split(Path::new("/dir.ZIP")),
+            Some((PathBuf::from("/dir"), ArchiveFormat::Zip))
+        );
+        assert_eq!(ArchiveFormat::split(Path::new("/.tar")), None);
+        assert_eq!(ArchiveFormat::split(Path::new("/dir.txt")), None);
+    }
+
+    #[tokio::test]
+    async fn test_tar() {
+        let fs = tree().await;
+        for format in [ArchiveFormat::Tar, ArchiveFormat::TarGz] {
+            let data = archive(&fs, format).await;
+            let reader: Box<dyn Read> = if format == ArchiveFormat::TarGz {
+                Box::new(flate2::read::GzDecoder::new(&data[..]))
+            } else {
+                Box::new(&data[..])
+            };
+            let mut tar = tar::Archive::new(reader);
+            let mut names = Vec::new();
+            for entry in tar.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                if name == "dir/a.txt" {
+                    let mut content = String::new();
+                    entry.read_to_string(&mut content).unwrap();
+                    assert_eq!(content, "hello");
+                }
+                names.push(name);
+            }
+            assert_eq!(names.len(), 4);
+            assert_eq!(names[..3], ["dir/", "dir/a.txt", "dir/sub/"]);
+            assert_eq!(names[3], format!("dir/sub/{}.txt", "x".repeat(120)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zip() {
+        let fs = tree().await;
+        let data = archive(&fs, ArchiveFormat::Zip).await;
+        let mut zip = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.len(), 4);
+        let mut content = String::new();
+        zip.by_name("dir/a.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello");
+        assert!(zip.by_name("dir/sub/").unwrap().is_dir());
+        let long = format!("dir/sub/{}.txt", "x".repeat(120));
+        assert_eq!(zip.by_name(&long).unwrap().size(), 9);
+    }
+
+    // 读出所有条目的名称和内容，读取时检查CRC
+    fn read_zip(data: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let mut zip = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut file = zip.by_index(i).unwrap();
+                let mut content = Vec::new();
+                file.read_to_end(&mut content).unwrap();
+                (file.name().to_string(), content)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_zip64() {
+        let fs = tree().await;
+        let items = collect(&fs, Path::new("/dir")).await.unwrap();
+        let mut normal = Vec::new();
+        write_zip(&fs, &items, &mut normal, ZIP64_LIMIT)
+            .await
+            .unwrap();
+        // 所有大小和偏移都使用ZIP64扩展，内容与普通格式相同
+        let mut forced = Vec::new();
+        write_zip(&fs, &items, &mut forced, 0).await.unwrap();
+        assert!(forced.windows(4).any(|w| w == 0x06064b50u32.to_le_bytes()));
+        assert!(!normal.windows(4).any(|w| w == 0x06064b50u32.to_le_bytes()));
+        let entries = read_zip(forced);
+        assert_eq!(entries, read_zip(normal));
+        assert_eq!(entries[1], ("dir/a.txt".to_string(), b"hello".to_vec()));
+
+        // 只有后面的条目的偏移超过限制
+        let mut mixed = Vec::new();
+        write_zip(&fs, &items, &mut mixed, 100).await.unwrap();
+        assert_eq!(read_zip(mixed), entries);
+    }
+
+    #[tokio::test]
+    async fn test_zip_changed_file() {
+        let fs = tree().await;
+        let items = collect(&fs, Path::new("/dir")).await.unwrap();
+        // 收集之后文件变大或变小，条目的大小与内容不再一致
+        for data in [&b"hello world"[..], b"hi"] {
+            put(&fs, "/dir/a.txt", data).await;
+            let mut out = Vec::new();
+            let err = write_zip(&fs, &items, &mut out, ZIP64_LIMIT)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
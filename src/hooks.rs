@@ -0,0 +1,30 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+// 触发事件的会话信息
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub peer: SocketAddr,
+    pub username: Option<String>,
+}
+
+// 会话中发生的事件，路径均为客户端看到的路径
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    Connected,
+    Disconnected,
+    LoggedIn,
+    LoginFailed { username: String },
+    Downloaded { path: PathBuf },
+    Uploaded { path: PathBuf },
+    Deleted { path: PathBuf },
+    DirectoryCreated { path: PathBuf },
+    DirectoryRemoved { path: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+    Copied { from: PathBuf, to: PathBuf },
+}
+
+// 事件回调，在会话任务中同步调用，耗时操作应自行转交其他任务处理
+pub trait EventHooks: Send + Sync {
+    fn on_event(&self, session: &SessionInfo, event: &Event);
+}
@@ -0,0 +1,51 @@
+mod ascii;
+pub mod auth;
+mod checksum;
+pub mod config;
+mod connections;
+mod data;
+mod download;
+pub mod hooks;
+mod listing;
+mod message;
+mod mode;
+mod passive;
+mod path;
+mod quota;
+mod server;
+mod session;
+mod site;
+pub mod storage;
+mod throttle;
+mod trash;
+
+pub use auth::Authenticator;
+pub use config::{Config, Quota, S3Config, StorageKind, UserConfig};
+pub use hooks::{Event, EventHooks, SessionInfo};
+pub use server::{Server, ServerBuilder, ServerHandle};
+#[cfg(feature = "s3")]
+pub use storage::S3Fs;
+pub use storage::{LocalFs, MemoryFs, StorageBackend};
+
+#[macro_export]
+macro_rules! mydbg {
+    ($($val:expr),+ $(,)?) => {
+        if cfg!(debug_assertions) {
+            dbg!($($val),+)
+        } else {
+            ($($val),+)
+        }
+    };
+    ($val:expr $(,)?) => {
+        if cfg!(debug_assertions) {
+            dbg!($val)
+        } else {
+            $val
+        }
+    };
+    () => {
+        if cfg!(debug_assertions) {
+            dbg!()
+        }
+    }
+}
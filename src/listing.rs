@@ -0,0 +1,186 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::storage::{DirEntry, FileKind};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// UTC时间，(年, 月, 日, 时, 分, 秒)
+pub fn civil_time(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Howard Hinnant的civil_from_days算法
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (
+        year,
+        month,
+        day,
+        (rem / 3600) as u32,
+        (rem % 3600 / 60) as u32,
+        (rem % 60) as u32,
+    )
+}
+
+// civil_time的逆运算，日期无效时返回None
+pub fn system_time(
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> Option<SystemTime> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 {
+        return None;
+    }
+    if minute > 59 || second > 59 {
+        return None;
+    }
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    let time = if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    };
+    // 排除2月30日之类的日期
+    (civil_time(time).2 == day).then_some(time)
+}
+
+// RFC 3659的时间格式YYYYMMDDHHMMSS，UTC
+pub fn format_timeval(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil_time(time);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+// 解析YYYYMMDDHHMMSS，忽略小数部分的秒
+pub fn parse_timeval(s: &str) -> Option<SystemTime> {
+    let s = s.split_once('.').map_or(s, |(s, _)| s);
+    if s.len() != 14 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let num = |range: std::ops::Range<usize>| s[range].parse::<u32>().ok();
+    system_time(
+        num(0..4)? as i64,
+        num(4..6)?,
+        num(6..8)?,
+        num(8..10)?,
+        num(10..12)?,
+        num(12..14)?,
+    )
+}
+
+fn permissions(kind: FileKind, mode: Option<u32>) -> String {
+    let mode = mode.unwrap_or(match kind {
+        FileKind::Dir => 0o755,
+        _ => 0o644,
+    });
+    let mut s = String::with_capacity(10);
+    s.push(match kind {
+        FileKind::Dir => 'd',
+        FileKind::Symlink => 'l',
+        FileKind::File => '-',
+    });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+// 生成与`ls -l`格式相同的一行
+pub fn list_line(entry: &DirEntry, now: SystemTime) -> String {
+    let meta = &entry.metadata;
+    let modified = meta.modified.unwrap_or(UNIX_EPOCH);
+    let (year, month, day, hour, minute, _) = civil_time(modified);
+    // 半年内的文件显示时间，否则显示年份
+    let recent = now
+        .duration_since(modified)
+        .is_ok_and(|age| age < Duration::from_secs(180 * 86400));
+    let date = if recent {
+        format!(
+            "{} {:>2} {:02}:{:02}",
+            MONTHS[month as usize - 1],
+            day,
+            hour,
+            minute
+        )
+    } else {
+        format!("{} {:>2}  {}", MONTHS[month as usize - 1], day, year)
+    };
+    format!(
+        "{} 1 ftp ftp {:>12} {} {}\r\n",
+        permissions(meta.kind, meta.mode),
+        meta.len,
+        date,
+        entry.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Metadata;
+
+    #[test]
+    fn test_civil_time() {
+        assert_eq!(civil_time(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+        let t = UNIX_EPOCH + Duration::from_secs(951_782_400 + 3661);
+        assert_eq!(civil_time(t), (2000, 2, 29, 1, 1, 1));
+        assert_eq!(system_time(2000, 2, 29, 1, 1, 1), Some(t));
+        assert_eq!(system_time(1970, 1, 1, 0, 0, 0), Some(UNIX_EPOCH));
+        assert_eq!(system_time(2001, 2, 29, 0, 0, 0), None);
+        assert_eq!(system_time(2001, 13, 1, 0, 0, 0), None);
+
+        assert_eq!(parse_timeval("20000229010101"), Some(t));
+        assert_eq!(parse_timeval("20000229010101.123"), Some(t));
+        assert_eq!(format_timeval(t), "20000229010101");
+        assert_eq!(parse_timeval("200002290101"), None);
+        assert_eq!(parse_timeval("+0000229010101"), None);
+    }
+
+    #[test]
+    fn test_list_line() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let entry = DirEntry {
+            name: "a.txt".into(),
+            metadata: Metadata {
+                kind: FileKind::File,
+                len: 42,
+                modified: Some(modified),
+                mode: Some(0o640),
+            },
+        };
+        assert_eq!(
+            list_line(&entry, modified + Duration::from_secs(60)),
+            "-rw-r----- 1 ftp ftp           42 Nov 14 22:13 a.txt\r\n"
+        );
+        assert_eq!(
+            list_line(&entry, modified + Duration::from_secs(365 * 86400)),
+            "-rw-r----- 1 ftp ftp           42 Nov 14  2023 a.txt\r\n"
+        );
+    }
+}
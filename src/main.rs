@@ -1,52 +1,39 @@
-use std::{env::set_current_dir, error::Error};
+use std::error::Error;
 
-use tokio::net::TcpListener;
+use clap::Parser;
+use ftpserver::Server;
+
+mod cli;
 
-mod message;
-mod path;
-mod server;
-mod session;
-#[macro_export]
-macro_rules! mydbg {
-    ($($val:expr),+ $(,)?) => {
-        if cfg!(debug_assertions) {
-            dbg!($($val),+)
-        } else {
-            ($($val),+)
-        }
-    };
-    ($val:expr $(,)?) => {
-        if cfg!(debug_assertions) {
-            dbg!($val)
-        } else {
-            $val
-        }
-    };
-    () => {
-        if cfg!(debug_assertions) {
-            dbg!()
-        }
-    }
-}
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    if let Some(specified_dir) = std::env::args().nth(1) {
-        set_current_dir(specified_dir)?;
-    } else {
-        #[cfg(target_os = "linux")]
-        set_current_dir("/var/ftp")?; // 设置当前目录为/var/ftp
-        #[cfg(target_os = "windows")]
-        set_current_dir("C:\\ftp")?; // 设置当前目录为C:\ftp
-    }
+    let cli = cli::Cli::parse();
+    let config = match cli.config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("ftpserver: {}", e);
+            std::process::exit(2);
+        }
+    };
     #[cfg(debug_assertions)]
     let env = env_logger::Env::default().filter_or("RUST_LOG", "debug");
 
     #[cfg(not(debug_assertions))]
     let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
 
-    env_logger::init_from_env(env);
-    let listener = TcpListener::bind("0.0.0.0:2121").await?;
-    log::info!("Listening on {}", listener.local_addr()?);
-    let mut server = server::Server::new(listener);
-    server.run().await
+    // 配置中指定的日志级别优先于RUST_LOG
+    let mut logger = env_logger::Builder::from_env(env);
+    if let Some(level) = &config.log_level {
+        logger.parse_filters(level);
+    }
+    logger.init();
+
+    let server = Server::builder(config)
+        .handle_signals(true)
+        // 收到SIGHUP时按启动时的参数重新读取配置文件和用户数据库
+        .on_reload(move || cli.config())
+        .build()
+        .await?;
+    server.run().await?;
+    Ok(())
 }
@@ -0,0 +1,303 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use async_compression::{
+    Level,
+    tokio::{bufread::ZlibDecoder, write::ZlibEncoder},
+};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, ReadBuf};
+
+pub type ModeReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type ModeWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+// 传输模式，决定数据连接上的编码方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    Stream,
+    // RFC 959块模式
+    Block,
+    // zlib压缩，MODE Z
+    Deflate,
+}
+
+pub const DEFAULT_LEVEL: u32 = 6;
+
+impl TransferMode {
+    // 包装数据连接的读取端，解码出原始数据
+    pub fn reader<R: AsyncRead + Send + Unpin + 'static>(self, inner: R) -> ModeReader {
+        match self {
+            Self::Stream => Box::new(inner),
+            Self::Block => Box::new(BlockReader::new(inner)),
+            Self::Deflate => Box::new(ZlibDecoder::new(BufReader::new(inner))),
+        }
+    }
+
+    // 包装数据连接的写入端，必须调用shutdown才能写入结束标记
+    // offset为传输开始时在文件中的位置，用于计算重启标记
+    pub fn writer<W: AsyncWrite + Send + Unpin + 'static>(
+        self,
+        inner: W,
+        level: u32,
+        offset: u64,
+    ) -> ModeWriter {
+        match self {
+            Self::Stream => Box::new(inner),
+            Self::Block => Box::new(BlockWriter::new(inner, offset)),
+            Self::Deflate => Box::new(ZlibEncoder::with_quality(
+                inner,
+                Level::Precise(level as i32),
+            )),
+        }
+    }
+}
+
+// 块描述符
+const EOF: u8 = 64;
+const RESTART_MARKER: u8 = 16;
+const MAX_BLOCK: usize = 0xFFFF;
+// 每传输这么多数据插入一个重启标记，内容为文件中的位置，可以直接用于REST
+const MARKER_INTERVAL: u64 = 1 << 20;
+
+fn block(descriptor: u8, data: &[u8], out: &mut Vec<u8>) {
+    out.push(descriptor);
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+pub struct BlockWriter<W> {
+    inner: W,
+    // 已编码但还未写入的数据
+    pending: Vec<u8>,
+    written: u64,
+    next_marker: u64,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> BlockWriter<W> {
+    // 从文件的offset处开始传输，如REST之后的RETR
+    pub fn new(inner: W, offset: u64) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            written: offset,
+            next_marker: (offset / MARKER_INTERVAL + 1) * MARKER_INTERVAL,
+            finished: false,
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for BlockWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if this.written >= this.next_marker {
+            let marker = this.written.to_string();
+            block(RESTART_MARKER, marker.as_bytes(), &mut this.pending);
+            this.next_marker += MARKER_INTERVAL;
+        }
+        let len = buf.len().min(MAX_BLOCK);
+        block(0, &buf[..len], &mut this.pending);
+        this.written += len as u64;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.finished {
+            ready!(this.poll_pending(cx))?;
+            block(EOF, &[], &mut this.pending);
+            this.finished = true;
+        }
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+pub struct BlockReader<R> {
+    inner: R,
+    header: [u8; 3],
+    header_len: usize,
+    descriptor: u8,
+    // 当前块中还未读取的字节数
+    remaining: usize,
+    scratch: Box<[u8]>,
+}
+
+impl<R: AsyncRead + Unpin> BlockReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            header: [0; 3],
+            header_len: 0,
+            descriptor: 0,
+            remaining: 0,
+            scratch: vec![0; 16 * 1024].into_boxed_slice(),
+        }
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Block mode transfer ended without an EOF block",
+    )
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for BlockReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // 缓冲区已满时不能读取，否则会被误判为连接提前结束
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.remaining > 0 {
+                let marker = this.descriptor & RESTART_MARKER != 0;
+                let mut len = this.remaining.min(this.scratch.len());
+                if !marker {
+                    len = len.min(buf.remaining());
+                }
+                let mut chunk = ReadBuf::new(&mut this.scratch[..len]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+                let n = chunk.filled().len();
+                if n == 0 {
+                    return Poll::Ready(Err(unexpected_eof()));
+                }
+                this.remaining -= n;
+                // 客户端发送的重启标记不属于文件内容
+                if marker {
+                    continue;
+                }
+                buf.put_slice(chunk.filled());
+                return Poll::Ready(Ok(()));
+            }
+            if this.descriptor & EOF != 0 {
+                return Poll::Ready(Ok(()));
+            }
+            while this.header_len < 3 {
+                let mut header = ReadBuf::new(&mut this.header[this.header_len..]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut header))?;
+                let n = header.filled().len();
+                if n == 0 {
+                    return Poll::Ready(Err(unexpected_eof()));
+                }
+                this.header_len += n;
+            }
+            this.header_len = 0;
+            this.descriptor = this.header[0];
+            this.remaining = u16::from_be_bytes([this.header[1], this.header[2]]) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    async fn roundtrip(mode: TransferMode, data: &[u8]) -> Vec<u8> {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let received = tokio::spawn(async move {
+            let mut encoded = Vec::new();
+            server.read_to_end(&mut encoded).await.unwrap();
+            encoded
+        });
+        let mut writer = mode.writer(client, 9, 0);
+        writer.write_all(data).await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+        let encoded = received.await.unwrap();
+        let mut decoded = Vec::new();
+        mode.reader(std::io::Cursor::new(encoded.clone()))
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, data);
+        encoded
+    }
+
+    #[tokio::test]
+    async fn test_block_mode() {
+        let encoded = roundtrip(TransferMode::Block, b"hello").await;
+        assert_eq!(encoded, b"\0\0\x05hello\x40\0\0");
+
+        // 超过一个块的数据，并包含重启标记
+        let data: Vec<u8> = (0..2 * MARKER_INTERVAL as usize).map(|i| i as u8).collect();
+        let encoded = roundtrip(TransferMode::Block, &data).await;
+        // 标记位于第一个使传输量达到间隔的块之后，内容为之前的数据量
+        let blocks = MARKER_INTERVAL.div_ceil(MAX_BLOCK as u64) as usize;
+        let marker = (blocks * MAX_BLOCK).to_string();
+        let pos = blocks * (MAX_BLOCK + 3);
+        assert_eq!(encoded[pos], RESTART_MARKER);
+        assert_eq!(&encoded[pos + 3..pos + 3 + marker.len()], marker.as_bytes());
+
+        // 缺少EOF块
+        let mut out = Vec::new();
+        let err = TransferMode::Block
+            .reader(&b"\0\0\x02hi"[..])
+            .read_to_end(&mut out)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // REST之后的传输，标记为文件中的位置
+        let offset = MARKER_INTERVAL - 10;
+        let mut writer = BlockWriter::new(Vec::new(), offset);
+        writer.write_all(&[0; 20]).await.unwrap();
+        writer.write_all(&[0; 5]).await.unwrap();
+        let marker = (offset + 20).to_string();
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.inner[23], RESTART_MARKER);
+        assert_eq!(&writer.inner[26..26 + marker.len()], marker.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_block_reader_empty_buf() {
+        let mut reader = BlockReader::new(&b"\0\0\x02hi\x40\0\0"[..]);
+        let mut empty = [0u8; 0];
+        assert_eq!(reader.read(&mut empty).await.unwrap(), 0);
+        // 空缓冲区的读取不影响之后的数据
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out, b"hi");
+    }
+
+    #[tokio::test]
+    async fn test_deflate_mode() {
+        let data = b"log line\n".repeat(1000);
+        let encoded = roundtrip(TransferMode::Deflate, &data).await;
+        assert!(encoded.len() < data.len() / 10);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+use tokio::net::{TcpListener, TcpSocket};
+
+use crate::config::Config;
+
+// 被动模式端口分配，所有会话共享
+pub struct PassivePorts {
+    range: Option<RangeInclusive<u16>>,
+    next: AtomicU16,
+    external_ip: Option<IpAddr>,
+}
+
+impl PassivePorts {
+    pub async fn new(config: &Config) -> io::Result<Self> {
+        let external_ip = match &config.pasv_address {
+            Some(host) => Some(Self::resolve(host).await?),
+            None => None,
+        };
+        if let Some(ip) = external_ip {
+            log::info!("Advertising passive address {}", ip);
+        }
+        let range = config.pasv_ports.clone();
+        let next = range.as_ref().map_or(0, |r| *r.start());
+        Ok(Self {
+            range,
+            next: AtomicU16::new(next),
+            external_ip,
+        })
+    }
+
+    async fn resolve(host: &str) -> io::Result<IpAddr> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(ip);
+        }
+        // PASV回复只能携带IPv4地址
+        tokio::net::lookup_host((host, 0))
+            .await?
+            .map(|addr| addr.ip())
+            .find(IpAddr::is_ipv4)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No IPv4 address found for passive host {}", host),
+                )
+            })
+    }
+
+    // 在回复中通告的地址，未配置时使用控制连接的本地地址
+    pub fn advertised_ip(&self, local_ip: IpAddr) -> IpAddr {
+        self.external_ip.unwrap_or(local_ip)
+    }
+
+    pub fn bind(&self, local_ip: IpAddr) -> io::Result<TcpListener> {
+        let Some(range) = &self.range else {
+            return Self::bind_port(SocketAddr::new(local_ip, 0));
+        };
+        let (start, end) = (*range.start(), *range.end());
+        let len = (end - start) as usize + 1;
+        // 轮询分配，端口被占用时尝试下一个
+        for _ in 0..len {
+            let port = self
+                .next
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |p| {
+                    Some(if p >= end || p < start { start } else { p + 1 })
+                })
+                .unwrap();
+            match Self::bind_port(SocketAddr::new(local_ip, port)) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => log::debug!("Passive port {} unavailable: {}", port, e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "No passive ports available",
+        ))
+    }
+
+    fn bind_port(addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        // 允许重用处于TIME_WAIT状态的端口
+        socket.set_reuseaddr(true)?;
+        socket.bind(addr)?;
+        socket.listen(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_in_range() {
+        let config = Config {
+            pasv_ports: Some(40000..=40002),
+            ..Default::default()
+        };
+        let ports = PassivePorts::new(&config).await.unwrap();
+        let ip = "127.0.0.1".parse().unwrap();
+        let a = ports.bind(ip).unwrap();
+        let b = ports.bind(ip).unwrap();
+        let c = ports.bind(ip).unwrap();
+        let mut got = [
+            a.local_addr().unwrap().port(),
+            b.local_addr().unwrap().port(),
+            c.local_addr().unwrap().port(),
+        ];
+        got.sort();
+        assert_eq!(got, [40000, 40001, 40002]);
+        // 范围内端口全部被占用
+        assert!(ports.bind(ip).is_err());
+        // 释放后可以重新分配
+        drop(b);
+        assert!(ports.bind(ip).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_external_address() {
+        let config = Config {
+            pasv_address: Some("203.0.113.7".to_string()),
+            ..Default::default()
+        };
+        let ports = PassivePorts::new(&config).await.unwrap();
+        let local: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(
+            ports.advertised_ip(local),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+    }
+}
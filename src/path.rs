@@ -1,138 +1,182 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use crate::mydbg;
 
+// 可以作为只读目录浏览的压缩包
+const ARCHIVE_EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+pub fn is_archive(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy().to_lowercase();
+        ARCHIVE_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+    })
+}
+
+// 将路径拆分为第一个压缩包的路径和压缩包内的路径，如/a/b.zip/c -> (/a/b.zip, /c)
+pub fn split_archive(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let mut archive = PathBuf::from("/");
+    let mut components = path.components();
+    for component in components.by_ref() {
+        if let Component::Normal(name) = component {
+            archive.push(name);
+            if is_archive(&archive) {
+                let inner = Path::new("/").join(components.as_path());
+                return Some((archive, inner));
+            }
+        }
+    }
+    None
+}
+
+// 处理客户端看到的虚拟路径，根目录为"/"，由存储后端映射到实际位置
+// 用户设置了主目录时，客户端的"/"对应存储中的主目录，不能访问主目录之外的路径
 pub struct PathHandler {
+    // 存储中的路径
     pwd: PathBuf,
     root: PathBuf,
 }
 
+impl Default for PathHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PathHandler {
-    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
-        let root = root.into();
+    pub fn new() -> Self {
+        Self::with_root(PathBuf::from("/"))
+    }
+
+    pub fn with_root(root: PathBuf) -> Self {
         Self {
-            root: root.clone(),
-            pwd: root,
+            pwd: root.clone(),
+            root,
         }
     }
 
-    fn set_pwd(&mut self, new_pwd: PathBuf) {
+    // new_pwd为resolve返回的存储中的路径
+    pub fn set_pwd(&mut self, new_pwd: PathBuf) {
         self.pwd = new_pwd;
     }
-    pub fn cd(&mut self, new_pwd: impl AsRef<Path>) -> std::io::Result<()> {
-        let client_path = new_pwd.as_ref();
-        let client_path = client_path.strip_prefix("/").unwrap_or(&client_path);
-        let server_path = self.to_server_path(client_path)?;
-        if !mydbg!(&server_path).is_absolute() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Path must be absolute",
-            ));
-        }
-        if !self.is_within_root(&server_path) {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "Path is outside of the root directory",
-            ));
-        }
-        self.set_pwd(server_path);
-        Ok(())
-    }
 
+    // 客户端看到的当前目录
     pub fn get_pwd(&self) -> PathBuf {
-        let path = self.to_client_path(&self.pwd);
-        if path.as_os_str().is_empty() {
-            PathBuf::from("/")
-        } else {
-            path
-        }
+        self.client_path(&self.pwd)
     }
 
-    pub fn is_within_root(&self, path: &PathBuf) -> bool {
-        mydbg!(path, &self.root);
+    // 存储中的路径是否在客户端可以访问的范围内
+    pub fn contains(&self, path: &Path) -> bool {
         path.starts_with(&self.root)
     }
-    pub fn to_client_path(&self, path: impl AsRef<Path>) -> PathBuf {
-        let path = path.as_ref();
-        path.strip_prefix(&self.root).unwrap_or(path).to_path_buf()
-    }
 
-    pub fn to_server_path(&self, path: impl AsRef<Path>) -> std::io::Result<PathBuf> {
-        let path = self.non_canonicalized_path(path)?;
-        mydbg!(dunce::canonicalize(path))
+    // 将存储中的路径转换为客户端看到的路径
+    pub fn client_path(&self, path: &Path) -> PathBuf {
+        Path::new("/").join(path.strip_prefix(&self.root).unwrap_or(path))
     }
-    pub fn non_canonicalized_path(&self, path: impl AsRef<Path>) -> std::io::Result<PathBuf> {
-        let path = path.as_ref();
-        if path.starts_with("/") {
-            return Ok(self.root.join(path.strip_prefix("/").unwrap()));
+
+    // 将客户端路径解析为规范化的绝对虚拟路径，不允许超出根目录
+    pub fn resolve(&self, path: impl AsRef<Path>) -> std::io::Result<PathBuf> {
+        // Windows客户端可能使用反斜杠分隔
+        let path = path.as_ref().to_string_lossy().replace('\\', "/");
+        let joined = if path.starts_with('/') {
+            PathBuf::from(path)
+        } else {
+            self.get_pwd().join(path)
+        };
+        let mut resolved = PathBuf::from("/");
+        for component in mydbg!(&joined).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    if !resolved.pop() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::PermissionDenied,
+                            "Path is outside of the root directory",
+                        ));
+                    }
+                }
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            }
         }
-        mydbg!(path);
-        let server_path = self.pwd.join(path);
-        Ok(server_path)
+        Ok(self
+            .root
+            .join(resolved.strip_prefix("/").unwrap_or(&resolved)))
     }
 }
 
 #[cfg(test)]
-#[allow(unused)]
 mod tests {
     use super::*;
+
     #[test]
-    #[cfg(target_os = "linux")]
-    fn test_to_server_path() {
-        to_server_path("/var/ftp", "/dir1/doc.txt", "/var/ftp/dir1/doc.txt");
-        to_server_path("/var/ftp", "/dir1", "/var/ftp/dir1");
-        to_server_path("/var/ftp", "dir1/doc.txt", "/var/ftp/dir1/doc.txt");
-        to_server_path("/var/ftp", "dir1", "/var/ftp/dir1");
-    }
-    #[test]
-    #[cfg(windows)]
-    fn test_to_server_path() {
-        to_server_path(r"C:\\ftp", "/dir1\\doc.txt", r"C:\\ftp\\dir1\\doc.txt");
-        to_server_path(r"C:\\ftp", "/dir1", r"C:\\ftp\\dir1");
-        to_server_path(r"C:\\ftp", "dir1\\doc.txt", r"C:\\ftp\\dir1\\doc.txt");
-        to_server_path(r"C:\\ftp", "dir1", r"C:\\ftp\\dir1");
+    fn test_resolve() {
+        resolve("/", "/dir1/doc.txt", "/dir1/doc.txt");
+        resolve("/", "/dir1", "/dir1");
+        resolve("/", "dir1/doc.txt", "/dir1/doc.txt");
+        resolve("/", "dir1", "/dir1");
+        resolve("/", "", "/");
+        resolve("/dir1", "doc.txt", "/dir1/doc.txt");
+        resolve("/dir1", "../dir2/./a", "/dir2/a");
+        resolve("/", "dir1\\doc.txt", "/dir1/doc.txt");
+        let handler = PathHandler::new();
+        assert!(handler.resolve("..").is_err());
+        assert!(handler.resolve("/dir1/../..").is_err());
+
+        // 主目录之下的路径
+        let mut handler = PathHandler::with_root(PathBuf::from("/home/alice"));
+        assert_eq!(handler.resolve("/").unwrap(), Path::new("/home/alice"));
+        assert_eq!(
+            handler.resolve("a/../b").unwrap(),
+            Path::new("/home/alice/b")
+        );
+        assert!(handler.resolve("../bob").is_err());
+        handler.set_pwd(handler.resolve("dir1").unwrap());
+        assert_eq!(handler.get_pwd(), Path::new("/dir1"));
+        assert_eq!(
+            handler.resolve("doc.txt").unwrap(),
+            Path::new("/home/alice/dir1/doc.txt")
+        );
+        assert_eq!(
+            handler.client_path(Path::new("/home/alice/dir1/doc.txt")),
+            Path::new("/dir1/doc.txt")
+        );
     }
 
-    fn to_server_path(root: &str, path: &str, expected: &str) {
-        let handler = PathHandler::new(root);
-        let server_path = handler.to_server_path(path).unwrap();
-        assert_eq!(server_path, PathBuf::from(expected));
+    fn resolve(pwd: &str, path: &str, expected: &str) {
+        let mut handler = PathHandler::new();
+        handler.set_pwd(PathBuf::from(pwd));
+        assert_eq!(handler.resolve(path).unwrap(), PathBuf::from(expected));
     }
+
     #[test]
-    #[cfg(target_os = "linux")]
-    fn test_cd() {
-        cd("/var/ftp", "dir1/doc.txt", "/var/ftp/dir1/doc.txt");
-        cd("/var/ftp", "dir1", "/var/ftp/dir1");
-        cd("/var/ftp", "/dir1/doc.txt", "/var/ftp/dir1/doc.txt");
-        cd("/var/ftp", "/dir1", "/var/ftp/dir1");
-        let mut handler = PathHandler::new("/var/ftp");
-        handler.cd("dir1").unwrap();
-        assert_eq!(handler.pwd, Path::new("/var/ftp/dir1"));
-        handler.cd("..").unwrap();
-        assert_eq!(handler.pwd, Path::new("/var/ftp"));
-        handler.cd("/dir1").unwrap();
-        assert_eq!(handler.pwd, Path::new("/var/ftp/dir1"));
-        assert!(handler.cd("../..").is_err());
+    fn test_split_archive() {
+        let split = |path: &str| split_archive(Path::new(path));
+        assert_eq!(split("/a/b.txt"), None);
+        assert_eq!(
+            split("/rel/bundle.zip"),
+            Some((PathBuf::from("/rel/bundle.zip"), PathBuf::from("/")))
+        );
+        assert_eq!(
+            split("/rel/Bundle.TAR.GZ/docs/a.txt"),
+            Some((
+                PathBuf::from("/rel/Bundle.TAR.GZ"),
+                PathBuf::from("/docs/a.txt")
+            ))
+        );
     }
+
     #[test]
-    #[cfg(windows)]
-    fn test_cd() {
-        let mut handler = PathHandler::new(r"C:\\ftp");
-        handler.cd("dir1").unwrap();
-        assert_eq!(handler.pwd, Path::new(r"C:\\ftp\\dir1"));
-        handler.cd("..").unwrap();
-        assert_eq!(handler.pwd, Path::new(r"C:\\ftp"));
-        handler.cd("/dir1").unwrap();
-        assert_eq!(handler.pwd, Path::new(r"C:\\ftp\\dir1"));
-        assert!(handler.cd("../..").is_err());
-        cd(r"C:\\ftp", "dir1\\doc.txt", r"C:\\ftp\\dir1\\doc.txt");
-        cd(r"C:\\ftp", "dir1", r"C:\\ftp\\dir1");
-        cd(r"C:\\ftp", "/dir1\\doc.txt", r"C:\\ftp\\dir1\\doc.txt");
-        cd(r"C:\\ftp", "/dir1", r"C:\\ftp\\dir1");
-    }
-    fn cd(root: &str, path: &str, expected: &str) {
-        let mut handler = PathHandler::new(root);
-        handler.cd(path).unwrap();
-        assert_eq!(handler.pwd, PathBuf::from(expected));
+    fn test_set_pwd() {
+        let mut handler = PathHandler::new();
+        handler.set_pwd(handler.resolve("dir1").unwrap());
+        assert_eq!(handler.get_pwd(), Path::new("/dir1"));
+        handler.set_pwd(handler.resolve("..").unwrap());
+        assert_eq!(handler.get_pwd(), Path::new("/"));
+        handler.set_pwd(handler.resolve("/dir1").unwrap());
+        assert_eq!(
+            handler.resolve("doc.txt").unwrap(),
+            Path::new("/dir1/doc.txt")
+        );
+        assert!(handler.resolve("../..").is_err());
     }
 }
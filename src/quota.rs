@@ -0,0 +1,559 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use tokio::io::AsyncWrite;
+
+use crate::{
+    config::{Quota, UserConfig},
+    storage::{FileKind, StorageBackend},
+};
+
+// 文件总大小和文件数，目录本身不计入
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    // 单个文件的占用，len为None表示文件不存在
+    pub fn file(len: Option<u64>) -> Self {
+        len.map_or_else(Self::default, |bytes| Self { bytes, files: 1 })
+    }
+}
+
+// 统计路径下的占用，路径不存在时为0，不跟随符号链接
+pub async fn usage(storage: &dyn StorageBackend, path: &Path) -> io::Result<Usage> {
+    let metadata = match storage.metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Usage::default()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        return Ok(Usage::file(metadata.is_file().then_some(metadata.len)));
+    }
+    let mut total = Usage::default();
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in storage.list(&dir).await? {
+            match entry.metadata.kind {
+                FileKind::Dir => stack.push(dir.join(&entry.name)),
+                FileKind::File => {
+                    total.bytes += entry.metadata.len;
+                    total.files += 1;
+                }
+                FileKind::Symlink => {}
+            }
+        }
+    }
+    Ok(total)
+}
+
+fn exceeded(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::StorageFull, msg)
+}
+
+// 所有会话共享的配额状态，记录已登录用户的QuotaTracker
+// 配额限制的是用户主目录下的目录树，任何会话的修改都计入受影响的QuotaTracker
+#[derive(Default)]
+pub struct Quotas {
+    users: Mutex<Vec<(String, Weak<QuotaTracker>)>>,
+}
+
+impl Quotas {
+    // 同一用户的会话共享一个QuotaTracker，没有会话持有时在登录时重新统计占用
+    // 用户未设置任何配额时返回None
+    pub async fn for_user(
+        &self,
+        storage: &dyn StorageBackend,
+        name: &str,
+        user: &UserConfig,
+    ) -> io::Result<Option<Arc<QuotaTracker>>> {
+        let home = user.home.clone().unwrap_or_else(|| PathBuf::from("/"));
+        // dir_quotas中的目录是客户端看到的路径，相对于主目录
+        let limits: Vec<(PathBuf, Quota)> = user
+            .quota
+            .map(|quota| (home.clone(), quota))
+            .into_iter()
+            .chain(
+                user.dir_quotas
+                    .iter()
+                    .map(|(dir, quota)| (home.join(dir.strip_prefix("/").unwrap_or(dir)), *quota)),
+            )
+            .collect();
+        if limits.is_empty() {
+            return Ok(None);
+        }
+        if let Some(tracker) = self.find(name, &limits) {
+            return Ok(Some(tracker));
+        }
+        let mut trees = BTreeMap::new();
+        for (dir, _) in &limits {
+            trees.insert(dir.clone(), usage(storage, dir).await?);
+        }
+        // 统计期间同一用户的其他会话可能已经登录
+        if let Some(tracker) = self.find(name, &limits) {
+            return Ok(Some(tracker));
+        }
+        let tracker = Arc::new(QuotaTracker {
+            home,
+            limits,
+            trees: Mutex::new(trees),
+            trash: Mutex::default(),
+        });
+        let mut users = self.users.lock().unwrap();
+        users.retain(|(_, tracker)| tracker.strong_count() > 0);
+        users.push((name.to_string(), Arc::downgrade(&tracker)));
+        Ok(Some(tracker))
+    }
+
+    // 重新加载配置修改了限制时不再共享旧的
+    fn find(&self, name: &str, limits: &[(PathBuf, Quota)]) -> Option<Arc<QuotaTracker>> {
+        let users = self.users.lock().unwrap();
+        users
+            .iter()
+            .filter(|(user, _)| user == name)
+            .filter_map(|(_, tracker)| tracker.upgrade())
+            .find(|tracker| tracker.limits == limits)
+    }
+
+    // 是否有需要更新占用的用户
+    pub fn is_empty(&self) -> bool {
+        let users = self.users.lock().unwrap();
+        users.iter().all(|(_, tracker)| tracker.strong_count() == 0)
+    }
+
+    // 路径下的占用从before变为after
+    pub fn update(&self, path: &Path, before: Usage, after: Usage) {
+        let trackers: Vec<_> = {
+            let users = self.users.lock().unwrap();
+            users
+                .iter()
+                .filter_map(|(_, tracker)| tracker.upgrade())
+                .collect()
+        };
+        for tracker in trackers {
+            tracker.update(path, before, after);
+        }
+    }
+}
+
+// 用户的配额和主目录下各目录树的占用，同一用户的会话共享
+pub struct QuotaTracker {
+    home: PathBuf,
+    limits: Vec<(PathBuf, Quota)>,
+    trees: Mutex<BTreeMap<PathBuf, Usage>>,
+    // 回收站中的项按原路径计入该用户的配额
+    trash: Mutex<Vec<(PathBuf, Usage)>>,
+}
+
+impl QuotaTracker {
+    fn update(&self, path: &Path, before: Usage, after: Usage) {
+        let mut trees = self.trees.lock().unwrap();
+        for (dir, usage) in trees.iter_mut() {
+            if path.starts_with(dir) {
+                usage.bytes = (usage.bytes + after.bytes).saturating_sub(before.bytes);
+                usage.files = (usage.files + after.files).saturating_sub(before.files);
+            }
+        }
+    }
+
+    pub fn set_trash(&self, items: Vec<(PathBuf, Usage)>) {
+        *self.trash.lock().unwrap() = items;
+    }
+
+    // 目录的占用，包括该用户回收站中原来位于目录下的项
+    fn used(&self, trees: &BTreeMap<PathBuf, Usage>, dir: &Path) -> Usage {
+        let mut usage = trees.get(dir).copied().unwrap_or_default();
+        for (path, item) in self.trash.lock().unwrap().iter() {
+            if path.starts_with(dir) {
+                usage.bytes += item.bytes;
+                usage.files += item.files;
+            }
+        }
+        usage
+    }
+
+    // 从回收站恢复到path前检查配额，该项已计入配额，只要当前没有超出即可恢复
+    pub fn restore_allowed(&self, path: &Path) -> io::Result<()> {
+        let trees = self.trees.lock().unwrap();
+        for (dir, quota) in self.limits.iter().filter(|(dir, _)| path.starts_with(dir)) {
+            let usage = self.used(&trees, dir);
+            if quota.files.is_some_and(|files| usage.files > files) {
+                return Err(exceeded("File count quota exceeded"));
+            }
+            if quota.bytes.is_some_and(|bytes| usage.bytes > bytes) {
+                return Err(exceeded("Disk quota exceeded"));
+            }
+        }
+        Ok(())
+    }
+
+    // 上传最多允许写入的字节数，None表示不限制；已没有剩余配额时返回错误
+    pub fn upload_limit(
+        &self,
+        path: &Path,
+        existing: Option<u64>,
+        append: bool,
+    ) -> io::Result<Option<u64>> {
+        let trees = self.trees.lock().unwrap();
+        self.check(&trees, path, existing, append)
+    }
+
+    fn check(
+        &self,
+        trees: &BTreeMap<PathBuf, Usage>,
+        path: &Path,
+        existing: Option<u64>,
+        append: bool,
+    ) -> io::Result<Option<u64>> {
+        // 覆盖已有文件时释放原文件的空间
+        let freed = if append { 0 } else { existing.unwrap_or(0) };
+        let mut limit: Option<u64> = None;
+        for (dir, quota) in self.limits.iter().filter(|(dir, _)| path.starts_with(dir)) {
+            let usage = self.used(trees, dir);
+            if let Some(files) = quota.files
+                && existing.is_none()
+                && usage.files >= files
+            {
+                return Err(exceeded("File count quota exceeded"));
+            }
+            if let Some(bytes) = quota.bytes {
+                let remaining = (bytes + freed).saturating_sub(usage.bytes);
+                if remaining == 0 {
+                    return Err(exceeded("Disk quota exceeded"));
+                }
+                limit = Some(limit.map_or(remaining, |limit| limit.min(remaining)));
+            }
+        }
+        Ok(limit)
+    }
+
+    // 开始上传时检查配额并占用一个文件数，写入的数据随写随占用
+    // 防止同时进行的多个上传都按开始时的剩余配额写入，Reservation释放时归还占用
+    pub fn reserve(
+        self: &Arc<Self>,
+        path: &Path,
+        existing: Option<u64>,
+        append: bool,
+    ) -> io::Result<Reservation> {
+        let mut trees = self.trees.lock().unwrap();
+        self.check(&trees, path, existing, append)?;
+        let reserved = Usage {
+            bytes: 0,
+            files: existing.is_none() as u64,
+        };
+        add(&mut trees, path, reserved);
+        Ok(Reservation {
+            tracker: self.clone(),
+            path: path.to_path_buf(),
+            existing,
+            append,
+            reserved,
+        })
+    }
+
+    // SITE QUOTA的输出，每个配额一行
+    pub fn report(&self) -> Vec<String> {
+        let limit = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |n| n.to_string());
+        let trees = self.trees.lock().unwrap();
+        self.limits
+            .iter()
+            .map(|(dir, quota)| {
+                let usage = self.used(&trees, dir);
+                // 按客户端看到的路径显示
+                let dir = Path::new("/").join(dir.strip_prefix(&self.home).unwrap_or(dir));
+                format!(
+                    "{}: {} of {} bytes, {} of {} files",
+                    dir.display(),
+                    usage.bytes,
+                    limit(quota.bytes),
+                    usage.files,
+                    limit(quota.files)
+                )
+            })
+            .collect()
+    }
+}
+
+fn add(trees: &mut BTreeMap<PathBuf, Usage>, path: &Path, usage: Usage) {
+    for (dir, tree) in trees.iter_mut() {
+        if path.starts_with(dir) {
+            tree.bytes += usage.bytes;
+            tree.files += usage.files;
+        }
+    }
+}
+
+// 上传过程中占用的配额，上传结束后由调用方按实际大小更新
+pub struct Reservation {
+    tracker: Arc<QuotaTracker>,
+    path: PathBuf,
+    existing: Option<u64>,
+    append: bool,
+    reserved: Usage,
+}
+
+impl Reservation {
+    // 占用最多n字节，返回实际占用的字节数，没有剩余配额时返回错误
+    fn charge(&mut self, n: usize) -> io::Result<usize> {
+        let mut trees = self.tracker.trees.lock().unwrap();
+        // 已占用的文件数不再重复检查
+        let existing = self.existing.or(Some(0));
+        let len = match self
+            .tracker
+            .check(&trees, &self.path, existing, self.append)?
+        {
+            Some(limit) => limit.min(n as u64),
+            None => n as u64,
+        };
+        let charged = Usage {
+            bytes: len,
+            files: 0,
+        };
+        add(&mut trees, &self.path, charged);
+        self.reserved.bytes += len;
+        Ok(len as usize)
+    }
+
+    // 续传保留的中止上传时已接收的n字节一并计入，剩余配额不足时返回错误
+    pub fn resume(&mut self, n: u64) -> io::Result<()> {
+        let mut trees = self.tracker.trees.lock().unwrap();
+        let existing = self.existing.or(Some(0));
+        let limit = self
+            .tracker
+            .check(&trees, &self.path, existing, self.append)?;
+        if limit.is_some_and(|limit| limit < n) {
+            return Err(exceeded("Disk quota exceeded"));
+        }
+        let charged = Usage { bytes: n, files: 0 };
+        add(&mut trees, &self.path, charged);
+        self.reserved.bytes += n;
+        Ok(())
+    }
+
+    fn refund(&mut self, n: usize) {
+        let refund = Usage {
+            bytes: n as u64,
+            files: 0,
+        };
+        self.tracker.update(&self.path, refund, Usage::default());
+        self.reserved.bytes -= n as u64;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.tracker
+            .update(&self.path, self.reserved, Usage::default());
+    }
+}
+
+// 写入超过限制的部分时返回StorageFull错误，之前的数据正常写入
+pub struct QuotaWriter<W> {
+    inner: W,
+    reservation: Reservation,
+}
+
+impl<W> QuotaWriter<W> {
+    pub fn new(inner: W, reservation: Reservation) -> Self {
+        Self { inner, reservation }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for QuotaWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+        let len = self.reservation.charge(buf.len())?;
+        let poll = Pin::new(&mut self.inner).poll_write(cx, &buf[..len]);
+        // 未写入的部分归还
+        let written = match poll {
+            Poll::Ready(Ok(n)) => n,
+            _ => 0,
+        };
+        self.reservation.refund(len - written);
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::storage::MemoryFs;
+
+    #[tokio::test]
+    async fn test_quota() {
+        let storage = MemoryFs::new();
+        storage.mkdir(Path::new("/in")).await.unwrap();
+        for (path, data) in [("/a.txt", &b"hello"[..]), ("/in/b.txt", b"abc")] {
+            let mut file = storage.open_write(Path::new(path), false).await.unwrap();
+            file.write_all(data).await.unwrap();
+            file.shutdown().await.unwrap();
+        }
+
+        let mut user = UserConfig {
+            quota: Some(Quota {
+                bytes: Some(10),
+                files: None,
+            }),
+            ..Default::default()
+        };
+        user.dir_quotas.insert(
+            "/in".into(),
+            Quota {
+                bytes: None,
+                files: Some(1),
+            },
+        );
+        let quotas = Arc::new(Quotas::default());
+        let quota = quotas
+            .for_user(&storage, "alice", &user)
+            .await
+            .unwrap()
+            .unwrap();
+        // 同一用户的会话共享同一个QuotaTracker
+        let shared = quotas.for_user(&storage, "alice", &user).await.unwrap();
+        assert!(Arc::ptr_eq(&quota, &shared.unwrap()));
+        assert_eq!(
+            quota.report(),
+            [
+                "/: 8 of 10 bytes, 2 of unlimited files",
+                "/in: 3 of unlimited bytes, 1 of 1 files"
+            ]
+        );
+
+        // 覆盖a.txt时可以使用其原有空间
+        let a = Path::new("/a.txt");
+        assert_eq!(quota.upload_limit(a, Some(5), false).unwrap(), Some(7));
+        assert_eq!(quota.upload_limit(a, Some(5), true).unwrap(), Some(2));
+        assert!(
+            quota
+                .upload_limit(Path::new("/in/c.txt"), None, false)
+                .is_err()
+        );
+        assert_eq!(
+            quota
+                .upload_limit(Path::new("/in/b.txt"), Some(3), false)
+                .unwrap(),
+            Some(5)
+        );
+
+        quotas.update(a, Usage::file(Some(5)), Usage::file(Some(7)));
+        assert_eq!(
+            quota
+                .upload_limit(Path::new("/c.txt"), None, false)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::StorageFull
+        );
+        quotas.update(
+            Path::new("/in"),
+            usage(&storage, Path::new("/in")).await.unwrap(),
+            Usage::default(),
+        );
+        assert_eq!(quota.report()[1], "/in: 0 of unlimited bytes, 0 of 1 files");
+        assert_eq!(quota.report()[0], "/: 7 of 10 bytes, 1 of unlimited files");
+
+        // 同时进行的上传共享剩余的3字节
+        let c = Path::new("/c.txt");
+        let mut first = QuotaWriter::new(Vec::new(), quota.reserve(c, None, false).unwrap());
+        let mut second = QuotaWriter::new(Vec::new(), quota.reserve(c, None, false).unwrap());
+        first.write_all(b"ab").await.unwrap();
+        assert_eq!(
+            second.write_all(b"abcdef").await.unwrap_err().kind(),
+            io::ErrorKind::StorageFull
+        );
+        assert_eq!(second.inner, b"a");
+        assert_eq!(quota.report()[0], "/: 10 of 10 bytes, 3 of unlimited files");
+        // 上传结束后归还占用
+        drop((first, second));
+        assert_eq!(quota.report()[0], "/: 7 of 10 bytes, 1 of unlimited files");
+
+        // 没有会话持有时，下次登录重新统计
+        drop(quota);
+        assert!(quotas.is_empty());
+        let quota = quotas
+            .for_user(&storage, "alice", &user)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quota.report()[0], "/: 8 of 10 bytes, 2 of unlimited files");
+    }
+
+    #[tokio::test]
+    async fn test_home_quota() {
+        let storage = MemoryFs::new();
+        for dir in ["/alice", "/alice/in", "/bob"] {
+            storage.mkdir(Path::new(dir)).await.unwrap();
+        }
+        for path in ["/alice/a.txt", "/bob/b.txt"] {
+            let mut file = storage.open_write(Path::new(path), false).await.unwrap();
+            file.write_all(b"hello").await.unwrap();
+            file.shutdown().await.unwrap();
+        }
+        let user = |home: &str| {
+            let mut user = UserConfig {
+                home: Some(home.into()),
+                quota: Some(Quota {
+                    bytes: Some(10),
+                    files: None,
+                }),
+                ..Default::default()
+            };
+            let quota = Quota {
+                bytes: Some(4),
+                files: None,
+            };
+            user.dir_quotas.insert("/in".into(), quota);
+            user
+        };
+        let quotas = Quotas::default();
+        let (alice, bob) = (user("/alice"), user("/bob"));
+        let alice = quotas.for_user(&storage, "alice", &alice).await.unwrap();
+        let bob = quotas.for_user(&storage, "bob", &bob).await.unwrap();
+        let (alice, bob) = (alice.unwrap(), bob.unwrap());
+        // 目录按客户端看到的路径显示，只统计各自主目录下的文件
+        assert_eq!(
+            alice.report(),
+            [
+                "/: 5 of 10 bytes, 1 of unlimited files",
+                "/in: 0 of 4 bytes, 0 of unlimited files"
+            ]
+        );
+        quotas.update(
+            Path::new("/alice/in/c.txt"),
+            Usage::default(),
+            Usage::file(Some(3)),
+        );
+        assert_eq!(alice.report()[0], "/: 8 of 10 bytes, 2 of unlimited files");
+        assert_eq!(alice.report()[1], "/in: 3 of 4 bytes, 1 of unlimited files");
+        assert_eq!(bob.report()[0], "/: 5 of 10 bytes, 1 of unlimited files");
+        assert_eq!(
+            bob.upload_limit(Path::new("/bob/c.txt"), None, false)
+                .unwrap(),
+            Some(5)
+        );
+    }
+}
@@ -1,52 +1,435 @@
+use std::{
+    future::{Future, poll_fn},
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
 use tokio::{
-    net::TcpListener,
-    sync::{broadcast, mpsc},
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::{Notify, broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+
+use crate::{
+    auth::Authenticator,
+    config::{Config, StorageKind},
+    connections::ConnectionLimiter,
+    hooks::EventHooks,
+    message::SERVICE_NOT_AVAILABLE,
+    passive::PassivePorts,
+    quota::Quotas,
+    session::Session,
+    storage::{self, ArchiveCache, StorageBackend},
+    throttle::Bandwidth,
+    trash,
 };
 
-use crate::session::Session;
+type Reload = Box<dyn Fn() -> io::Result<Config> + Send + Sync>;
 
-pub struct Server {
-    ctrl_socket: TcpListener,
+// 所有会话共享的扩展组件
+#[derive(Default)]
+pub(crate) struct ServerContext {
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    pub hooks: Option<Arc<dyn EventHooks>>,
+    // 未设置时每个会话使用配置中root目录的本地存储
+    pub storage: Option<Arc<dyn StorageBackend>>,
+    // 浏览压缩包时解析结果的缓存
+    pub archives: ArchiveCache,
+    pub bandwidth: Bandwidth,
+    pub quotas: Arc<Quotas>,
 }
 
-impl Server {
-    pub fn new(listener: TcpListener) -> Self {
+pub struct ServerBuilder {
+    config: Config,
+    listeners: Vec<TcpListener>,
+    context: ServerContext,
+    reload: Option<Reload>,
+    handle_signals: bool,
+}
+
+impl ServerBuilder {
+    pub fn new(config: Config) -> Self {
         Self {
-            ctrl_socket: listener,
+            config,
+            listeners: Vec::new(),
+            context: ServerContext::default(),
+            reload: None,
+            handle_signals: false,
+        }
+    }
+
+    // 使用已经绑定的监听socket，设置后不再绑定配置中的listen地址
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    pub fn authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.context.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    pub fn hooks(mut self, hooks: impl EventHooks + 'static) -> Self {
+        self.context.hooks = Some(Arc::new(hooks));
+        self
+    }
+
+    // 替换默认的本地磁盘存储
+    pub fn storage(mut self, storage: impl StorageBackend + 'static) -> Self {
+        self.context.storage = Some(Arc::new(storage));
+        self
+    }
+
+    // 设置收到SIGHUP时重新读取配置的方法
+    pub fn on_reload(
+        mut self,
+        reload: impl Fn() -> io::Result<Config> + Send + Sync + 'static,
+    ) -> Self {
+        self.reload = Some(Box::new(reload));
+        self
+    }
+
+    // 是否响应Ctrl-C、SIGTERM和SIGHUP信号，嵌入其他程序时默认不处理
+    pub fn handle_signals(mut self, enable: bool) -> Self {
+        self.handle_signals = enable;
+        self
+    }
+
+    pub async fn build(mut self) -> io::Result<Server> {
+        if self.context.storage.is_some() {
+            self.config.validate_settings()?;
+        } else {
+            self.config.validate()?;
+            self.context.storage = storage::from_config(&self.config)?;
+        }
+        if self.listeners.is_empty() {
+            for addr in &self.config.listen {
+                self.listeners.push(TcpListener::bind(addr).await?);
+            }
+        }
+        for listener in &self.listeners {
+            log::info!("Listening on {}", listener.local_addr()?);
+        }
+        match (&self.context.storage, self.config.storage) {
+            (None, _) => log::info!("Serving {}", self.config.root.display()),
+            (Some(_), StorageKind::Memory) => log::info!("Serving files from memory"),
+            (Some(_), StorageKind::S3) => {
+                let bucket = self.config.s3.as_ref().map(|s3| s3.bucket.as_str());
+                log::info!("Serving S3 bucket {}", bucket.unwrap_or_default())
+            }
+            (Some(_), StorageKind::Local) => log::info!("Serving files from custom storage"),
         }
+        let passive = PassivePorts::new(&self.config).await?;
+        self.context.bandwidth.configure(&self.config);
+        Ok(Server {
+            ctrl_sockets: self.listeners,
+            config: watch::Sender::new(Arc::new(self.config)),
+            passive: Arc::new(passive),
+            context: Arc::new(self.context),
+            reload: self.reload,
+            handle_signals: self.handle_signals,
+            shutdown: Arc::new(Notify::new()),
+            reload_requested: Arc::new(Notify::new()),
+        })
+    }
+}
+
+pub struct Server {
+    ctrl_sockets: Vec<TcpListener>,
+    // 当前配置，会话通过接收端获知配置变化
+    config: watch::Sender<Arc<Config>>,
+    passive: Arc<PassivePorts>,
+    context: Arc<ServerContext>,
+    reload: Option<Reload>,
+    handle_signals: bool,
+    shutdown: Arc<Notify>,
+    reload_requested: Arc<Notify>,
+}
+
+// 在后台运行的服务器，可以等待其结束或请求关闭
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    shutdown: Arc<Notify>,
+    reload: Arc<Notify>,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl ServerHandle {
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    // 请求关闭服务器，与收到SIGTERM的处理相同
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    // 请求重新加载配置，与收到SIGHUP的处理相同
+    pub fn reload(&self) {
+        self.reload.notify_one();
+    }
+}
+
+impl Future for ServerHandle {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task)
+            .poll(cx)
+            .map(|res| res.unwrap_or_else(|e| Err(io::Error::other(e))))
+    }
+}
+
+impl Server {
+    pub fn builder(config: Config) -> ServerBuilder {
+        ServerBuilder::new(config)
+    }
+
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.ctrl_sockets.iter().map(|l| l.local_addr()).collect()
+    }
+
+    // 在后台任务中运行服务器
+    pub fn start(self) -> io::Result<ServerHandle> {
+        let local_addrs = self.local_addrs()?;
+        let shutdown = self.shutdown.clone();
+        let reload = self.reload_requested.clone();
+        let task = tokio::spawn(self.run());
+        Ok(ServerHandle {
+            local_addrs,
+            shutdown,
+            reload,
+            task,
+        })
+    }
+
+    // 在所有监听地址上等待新连接
+    async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        poll_fn(|cx| {
+            for listener in &self.ctrl_sockets {
+                if let Poll::Ready(res) = listener.poll_accept(cx) {
+                    return Poll::Ready(res);
+                }
+            }
+            Poll::Pending
+        })
+        .await
     }
 
-    pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn run(self) -> io::Result<()> {
+        let config = self.config.borrow().clone();
+        let mut passive = self.passive.clone();
+        let mut limiter =
+            ConnectionLimiter::new(config.max_connections, config.max_connections_per_ip);
         let (send, mut recv) = mpsc::channel(1);
         let (shutdown_send, _) = broadcast::channel(1);
+        let mut hangup = if self.handle_signals {
+            Self::reload_signal()?
+        } else {
+            None
+        };
+        let purge = tokio::spawn(Self::purge_trash(self.config.subscribe()));
+        if self.context.storage.is_none() {
+            tokio::spawn(Self::sweep_uploads(config.clone()));
+        }
 
         tokio::select! {
             // 主循环接受连接
             _ = async {
                 loop {
-                    let (socket, addr) = self.ctrl_socket.accept().await?;
-                    log::info!("Accepted connection from {}", addr);
+                    let (mut socket, addr) = tokio::select! {
+                        res = self.accept() => res?,
+                        _ = Self::wait_signal(&mut hangup) => {
+                            log::info!("Received SIGHUP");
+                            self.reload(&mut passive, &mut limiter).await;
+                            continue;
+                        }
+                        _ = self.reload_requested.notified() => {
+                            self.reload(&mut passive, &mut limiter).await;
+                            continue;
+                        }
+                    };
+                    let Some(guard) = limiter.acquire(addr.ip()) else {
+                        let (total, from_ip) = limiter.count(addr.ip());
+                        log::warn!(
+                            "Rejected connection from {}: {} active connections, {} from this address",
+                            addr,
+                            total,
+                            from_ip
+                        );
+                        // 在创建会话之前直接回复并关闭连接
+                        tokio::spawn(async move {
+                            let reply = format!("{} Too many connections\r\n", SERVICE_NOT_AVAILABLE);
+                            let _ = socket.write_all(reply.as_bytes()).await;
+                            let _ = socket.shutdown().await;
+                        });
+                        continue;
+                    };
+                    let (total, from_ip) = limiter.count(addr.ip());
+                    log::info!(
+                        "Accepted connection from {} ({} active connections, {} from this address)",
+                        addr,
+                        total,
+                        from_ip
+                    );
 
-                    let mut session = Session::new(socket);
                     let shutdown_notify = shutdown_send.subscribe();
+                    let mut session = Session::new(
+                        socket,
+                        self.config.subscribe(),
+                        passive.clone(),
+                        shutdown_notify,
+                        self.context.clone(),
+                    );
                     let send = send.clone();
 
                     tokio::spawn(async move {
-                        session.run(shutdown_notify, send).await
+                        let res = session.run(send).await;
+                        drop(guard);
+                        res
                     });
                 }
                 #[allow(unreachable_code)]
-                Ok::<_, Box<dyn std::error::Error>>(())
+                Ok::<_, io::Error>(())
             } => {}
 
-            // 响应Ctrl-C信号
-            _ = tokio::signal::ctrl_c() => {
+            // 响应Ctrl-C和SIGTERM信号
+            _ = Self::shutdown_signal(), if self.handle_signals => {
                 log::info!("Received shutdown signal, shutting down.");
             }
+            _ = self.shutdown.notified() => {
+                log::info!("Shutdown requested, shutting down.");
+            }
         }
+        // 不再接受新连接，通知所有会话关闭
+        purge.abort();
         drop(shutdown_send);
         drop(send);
         let _ = recv.recv().await; // 等待所有会话完成，所有发送端drop之后返回一个错误
         Ok(())
     }
+
+    // 定期清除所有用户回收站中的过期项，使用当前配置中的回收站设置
+    async fn purge_trash(config: watch::Receiver<Arc<Config>>) {
+        let mut interval = tokio::time::interval(trash::PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = config.borrow().clone();
+            if let Some(dir) = &config.trash_dir {
+                trash::purge_all(dir, config.trash_retention).await;
+            }
+        }
+    }
+
+    // 删除崩溃的进程在本地存储中留下的上传临时文件
+    async fn sweep_uploads(config: Arc<Config>) {
+        let root = config.root.clone();
+        let (keep_partial, max_age) = (config.keep_partial_uploads, config.stale_upload_age);
+        match tokio::task::spawn_blocking(move || {
+            storage::sweep_uploads(&root, keep_partial, max_age)
+        })
+        .await
+        {
+            Ok(Ok(0)) => {}
+            Ok(Ok(n)) => log::info!("Removed {} stale upload files", n),
+            Ok(Err(e)) => log::warn!(
+                "Failed to remove stale upload files in {}: {}",
+                config.root.display(),
+                e
+            ),
+            Err(e) => log::warn!("Failed to remove stale upload files: {}", e),
+        }
+    }
+
+    // 重新读取配置并原子地替换，新会话使用新配置，已有会话保留原配置
+    async fn reload(&self, passive: &mut Arc<PassivePorts>, limiter: &mut ConnectionLimiter) {
+        let Some(reload) = &self.reload else {
+            log::warn!("Configuration reload is not available");
+            return;
+        };
+        log::info!("Reloading configuration");
+        let config = match reload() {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!(
+                    "Failed to reload configuration, keeping current settings: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let new_passive = match PassivePorts::new(&config).await {
+            Ok(new_passive) => new_passive,
+            Err(e) => {
+                log::error!(
+                    "Failed to reload configuration, keeping current settings: {}",
+                    e
+                );
+                return;
+            }
+        };
+        if config.listen != self.config.borrow().listen {
+            log::warn!("Listen addresses changed, a restart is required for them to take effect");
+        }
+        if config.storage != self.config.borrow().storage {
+            log::warn!("Storage changed, a restart is required for it to take effect");
+        }
+        *passive = Arc::new(new_passive);
+        limiter.set_limits(config.max_connections, config.max_connections_per_ip);
+        self.context.bandwidth.configure(&config);
+        self.config.send_replace(Arc::new(config));
+        log::info!("Configuration reloaded");
+    }
+
+    #[cfg(unix)]
+    fn reload_signal() -> io::Result<Option<tokio::signal::unix::Signal>> {
+        use tokio::signal::unix::{SignalKind, signal};
+        signal(SignalKind::hangup()).map(Some)
+    }
+
+    #[cfg(not(unix))]
+    fn reload_signal() -> io::Result<Option<()>> {
+        Ok(None)
+    }
+
+    #[cfg(unix)]
+    async fn wait_signal(signal: &mut Option<tokio::signal::unix::Signal>) {
+        match signal {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn wait_signal(_signal: &mut Option<()>) {
+        std::future::pending().await
+    }
+
+    #[cfg(unix)]
+    async fn shutdown_signal() {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut term = match signal(SignalKind::terminate()) {
+            Ok(term) => term,
+            Err(e) => {
+                log::warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn shutdown_signal() {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
@@ -1,23 +1,83 @@
-use std::path::Path;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
-    process::Command,
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, watch},
+    time::Instant,
 };
 
-use crate::{message::*, mydbg, path::PathHandler};
+use crate::{
+    ascii::{self, TransferType},
+    auth,
+    checksum::{self, HashAlgorithm},
+    config::{self, Config},
+    data::DataStream,
+    download::{self, ArchiveFormat},
+    hooks::{Event, SessionInfo},
+    listing,
+    message::*,
+    mode::{self, TransferMode},
+    mydbg,
+    passive::PassivePorts,
+    path::{self, PathHandler},
+    quota::{self, QuotaTracker, QuotaWriter, Usage},
+    server::ServerContext,
+    site::{self, SiteCommand},
+    storage::{ArchiveFs, DirEntry, FileKind, LocalFs, Metadata, StorageBackend, WriteStream},
+    throttle::Rates,
+    trash::Trash,
+};
 
 pub struct Session {
     socket: TcpStream,
+    // 会话建立时的配置，重新加载配置不影响已有会话
+    config: Arc<Config>,
+    config_updates: watch::Receiver<Arc<Config>>,
+    passive: Arc<PassivePorts>,
+    shutdown: broadcast::Receiver<()>,
+    context: Arc<ServerContext>,
+    storage: Arc<dyn StorageBackend>,
+    // 使用本地存储时与storage为同一个后端
+    local: Option<Arc<LocalFs>>,
+    // 配置了回收站时，登录后为该用户的回收站
+    trash: Option<Trash>,
     logged: bool,
-    // root: PathBuf,
-    // working_dir: PathBuf,
+    username: Option<String>,
+    connected_at: Instant,
+    max_session: Option<Duration>,
     path_handler: PathHandler,
+    transfer_type: TransferType,
+    transfer_mode: TransferMode,
+    // MODE Z的压缩级别
+    deflate_level: u32,
+    // REST设置的下一次传输的起始位置
+    restart_offset: u64,
+    // OPTS HASH选择的算法
+    hash_algorithm: HashAlgorithm,
+    // RANG设置的下一次HASH的字节范围，不含结束位置，None表示到文件末尾
+    hash_range: Option<(u64, Option<u64>)>,
+    // SITE UMASK和SITE IDLE可以修改，初始为配置中的值
+    umask: u32,
+    idle_timeout: Duration,
+    // 用户设置了配额时为该用户所有会话共享的配额
+    quota: Option<Arc<QuotaTracker>>,
+    // 数据传输适用的全局和客户端IP限速
+    rates: Vec<Arc<Rates>>,
+    // 登录用户的限速
+    user_rates: Option<Arc<Rates>>,
     data_listener: Option<TcpListener>,
-    data_port: Option<TcpStream>,
+    data_port: Option<SocketAddr>,
+    // EPSV ALL之后只允许EPSV建立数据连接
+    epsv_all: bool,
     rename_from_path: Option<std::path::PathBuf>,
+    // SITE CPFR记录的复制源文件
+    copy_from_path: Option<PathBuf>,
 }
 macro_rules! logged {
     ($session:ident) => {
@@ -31,39 +91,80 @@ macro_rules! logged {
 }
 
 impl Session {
-    pub fn new(socket: TcpStream) -> Self {
+    pub fn new(
+        socket: TcpStream,
+        mut config_updates: watch::Receiver<Arc<Config>>,
+        passive: Arc<PassivePorts>,
+        shutdown: broadcast::Receiver<()>,
+        context: Arc<ServerContext>,
+    ) -> Self {
+        let config = config_updates.borrow_and_update().clone();
+        // 回收站需要直接操作本地文件
+        let mut local = None;
+        let mut storage = context.storage.clone().unwrap_or_else(|| {
+            let fs = Arc::new(
+                LocalFs::new(&config.root).keep_partial_uploads(config.keep_partial_uploads),
+            );
+            local = Some(fs.clone());
+            fs
+        });
+        if config.browse_archives {
+            storage = Arc::new(ArchiveFs::new(storage, context.archives.clone()));
+        }
+        let mut rates = vec![context.bandwidth.global()];
+        if let Ok(peer) = socket.peer_addr() {
+            rates.push(context.bandwidth.for_ip(peer.ip().to_canonical()));
+        }
         Self {
             socket,
+            max_session: config.max_session,
+            path_handler: PathHandler::new(),
+            transfer_type: TransferType::Binary,
+            transfer_mode: TransferMode::Stream,
+            deflate_level: mode::DEFAULT_LEVEL,
+            restart_offset: 0,
+            hash_algorithm: HashAlgorithm::Sha256,
+            hash_range: None,
+            umask: config.umask,
+            idle_timeout: config.idle_timeout,
+            quota: None,
+            rates,
+            user_rates: None,
+            storage,
+            local,
+            trash: None,
+            config,
+            config_updates,
+            passive,
+            shutdown,
+            context,
             logged: false,
-            path_handler: PathHandler::new(std::env::current_dir().unwrap()),
+            username: None,
+            connected_at: Instant::now(),
             data_listener: None,
             data_port: None,
+            epsv_all: false,
             rename_from_path: None,
+            copy_from_path: None,
         }
     }
-    pub async fn run(
-        &mut self,
-        mut shutdown: broadcast::Receiver<()>,
-        _close_complete: mpsc::Sender<()>,
-    ) -> std::io::Result<()> {
-        self.send_response(SERVICE_READY_FOR_NEW_USER, "Service ready for new user")
+    pub async fn run(&mut self, _close_complete: mpsc::Sender<()>) -> std::io::Result<()> {
+        self.emit(Event::Connected);
+        let banner = self.config.banner.clone();
+        self.send_response(SERVICE_READY_FOR_NEW_USER, banner)
             .await?;
-        tokio::select! {
-            res = self.process() => {
-                if let Err(e) = res {
-                    let _ = self.send_response(ACTION_ABORTED_LOCAL_ERROR, "Connection aborted").await;
-                    log::error!("Session error: {}", e);
-                }
-            }
-            _ = shutdown.recv() => {
-                log::info!("Received shutdown signal, closing session.");
-            }
+        if let Err(e) = self.process().await {
+            let _ = self
+                .send_response(ACTION_ABORTED_LOCAL_ERROR, "Connection aborted")
+                .await;
+            log::error!("Session error: {}", e);
         }
+        self.emit(Event::Disconnected);
         Ok(())
     }
     async fn process(&mut self) -> std::io::Result<()> {
         let mut buf = vec![0; 512];
-        while let Ok(n) = self.socket.read(&mut buf).await {
+        while let Ok(Some(n)) = self.read_command(&mut buf).await {
             if n == 0 {
                 break;
             }
@@ -74,7 +175,12 @@ impl Session {
                 Some(cmd) => cmd,
                 None => (s, ""),
             };
-            match cmdtype.to_uppercase().as_str() {
+            let cmdtype = cmdtype.to_uppercase();
+            // RNFR只对紧接着的RNTO有效
+            if cmdtype != "RNTO" {
+                self.rename_from_path = None;
+            }
+            match cmdtype.as_str() {
                 "USER" => self.user(args).await,
                 "PASS" => self.pass(args).await,
                 "ACCT" => self.acct(args).await,
@@ -83,10 +189,32 @@ impl Session {
                 "NLST" => self.nlst(args).await,
                 "LIST" => self.list(args).await,
                 "PASV" => self.pasv(args).await,
+                "EPSV" => self.epsv(args).await,
                 "RETR" => self.retr(args).await,
                 "TYPE" => self.r#type(args).await,
+                "REST" => self.rest(args).await,
+                "SIZE" => self.size(args).await,
+                "MDTM" => self.mdtm(args).await,
+                "MFMT" => self.mfmt(args).await,
+                "MFCT" => {
+                    self.send_response(
+                        COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER,
+                        "Setting creation time is not supported",
+                    )
+                    .await
+                }
+                "MFF" => self.mff(args).await,
+                "HASH" => self.hash(args).await,
+                "RANG" => self.rang(args).await,
+                "XCRC" => self.xhash(args, HashAlgorithm::Crc32).await,
+                "XMD5" => self.xhash(args, HashAlgorithm::Md5).await,
+                "XSHA1" => self.xhash(args, HashAlgorithm::Sha1).await,
+                "XSHA256" => self.xhash(args, HashAlgorithm::Sha256).await,
                 "STOR" => self.stor(args).await,
+                "APPE" => self.appe(args).await,
                 "STRU" => self.stru(args).await,
+                "MODE" => self.mode(args).await,
+                "FEAT" => self.feat(args).await,
                 "DELE" => self.dele(args).await,
                 "RMD" => self.rmd(args).await,
                 "MKD" => self.mkd(args).await,
@@ -94,6 +222,9 @@ impl Session {
                 "RNTO" => self.rnto(args).await,
                 "OPTS" => self.opts(args).await,
                 "PORT" => self.port(args).await,
+                "EPRT" => self.eprt(args).await,
+                "REIN" => self.rein(args).await,
+                "SITE" => self.site(args).await,
                 "NOOP" => self.send_response(COMMAND_OK, "NOOP").await,
                 "QUIT" => {
                     self.send_response(
@@ -113,6 +244,77 @@ impl Session {
         Ok(())
     }
 
+    // 读取下一条命令，空闲超时、会话到期、账号被删除或服务器关闭时返回None
+    async fn read_command(&mut self, buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+        let deadline = self.deadline();
+        let (code, msg) = tokio::select! {
+            res = self.socket.read(buf) => return res.map(Some),
+            _ = tokio::time::sleep(self.idle_timeout) => {
+                (SERVICE_NOT_AVAILABLE, "Timeout, closing control connection")
+            }
+            _ = Self::sleep_until(deadline) => {
+                (SERVICE_NOT_AVAILABLE, "Maximum session time exceeded, closing control connection")
+            }
+            // 发送端drop后recv立即返回，之后每次读取命令都会走到这里
+            _ = self.shutdown.recv() => {
+                (SERVICE_NOT_AVAILABLE, "Service shutting down")
+            }
+            _ = Self::wait_revoked(
+                &mut self.config_updates,
+                self.username.as_deref().filter(|_| self.logged),
+            ) => {
+                (SERVICE_NOT_AVAILABLE, "Account removed, closing control connection")
+            }
+        };
+        log::info!("{} for {}", msg, self.socket.peer_addr()?);
+        self.send_response(code, msg).await?;
+        Ok(None)
+    }
+
+    // 等待重新加载的配置中删除当前登录的用户
+    async fn wait_revoked(updates: &mut watch::Receiver<Arc<Config>>, user: Option<&str>) {
+        let Some(user) = user else {
+            return std::future::pending().await;
+        };
+        let revoked =
+            |config: &Config| config.users_file.is_some() && !config.users.contains_key(user);
+        if revoked(&updates.borrow()) {
+            return;
+        }
+        while updates.changed().await.is_ok() {
+            if revoked(&updates.borrow_and_update()) {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+
+    // 会话到期时间，未限制最长会话时间时为None
+    fn deadline(&self) -> Option<Instant> {
+        self.max_session.map(|d| self.connected_at + d)
+    }
+
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        let Some(hooks) = &self.context.hooks else {
+            return;
+        };
+        let Ok(peer) = self.socket.peer_addr() else {
+            return;
+        };
+        let session = SessionInfo {
+            peer,
+            username: self.username.clone().filter(|_| self.logged),
+        };
+        hooks.on_event(&session, &event);
+    }
+
     pub async fn send_response(
         &mut self,
         code: impl AsRef<str>,
@@ -124,15 +326,134 @@ impl Session {
         self.socket.flush().await?;
         Ok(())
     }
-    async fn user(&mut self, _s: &str) -> std::io::Result<()> {
-        log::debug!("user: {}", _s);
+
+    // 多行响应，首行和末行带响应码，中间各行以空格开头
+    async fn send_multiline(&mut self, code: &str, lines: &[String]) -> io::Result<()> {
+        let mut response = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i == 0 && lines.len() > 1 {
+                response.push_str(&format!("{}-{}\r\n", code, line));
+            } else if i + 1 == lines.len() {
+                response.push_str(&format!("{} {}\r\n", code, line));
+            } else {
+                response.push_str(&format!(" {}\r\n", line));
+            }
+        }
+        log::debug!("Sending response: {}", response);
+        self.socket.write_all(response.as_bytes()).await?;
+        self.socket.flush().await
+    }
+    async fn user(&mut self, s: &str) -> std::io::Result<()> {
+        log::debug!("user: {}", s);
+        self.username = Some(s.to_string());
         self.send_response(USER_NAME_OK, "user name ok. need password.")
             .await
     }
-    async fn pass(&mut self, _s: &str) -> std::io::Result<()> {
+    async fn pass(&mut self, s: &str) -> std::io::Result<()> {
+        let username = self.username.clone().unwrap_or_default();
+        let accepted = match &self.context.authenticator {
+            Some(authenticator) => authenticator
+                .authenticate(&username, s)
+                .await
+                .unwrap_or_else(|e| {
+                    log::error!("Authentication error for user {}: {}", username, e);
+                    false
+                }),
+            // Argon2校验较慢，不在运行时线程上执行
+            None => {
+                let (config, name, password) =
+                    (self.config.clone(), username.clone(), s.to_string());
+                tokio::task::spawn_blocking(move || auth::check_users(&config, &name, &password))
+                    .await
+                    .unwrap_or(false)
+            }
+        };
+        if !accepted {
+            log::info!("Login failed for user {}", username);
+            self.logged = false;
+            self.emit(Event::LoginFailed { username });
+            return self.send_response(NOT_LOGGED_IN, "Login incorrect").await;
+        }
+        // 用户单独设置的最长会话时间优先于全局设置
+        self.max_session = self
+            .config
+            .users
+            .get(&username)
+            .and_then(|user| user.max_session)
+            .or(self.config.max_session);
+        let home = self
+            .config
+            .users
+            .get(&username)
+            .and_then(|user| user.home.clone());
+        self.path_handler = match home {
+            Some(home) => {
+                if let Err(e) = self.create_home(&home).await {
+                    log::error!(
+                        "Failed to create home directory {} for user {}: {}",
+                        home.display(),
+                        username,
+                        e
+                    );
+                    self.logged = false;
+                    return self
+                        .send_response(ACTION_ABORTED_LOCAL_ERROR, "Cannot create home directory")
+                        .await;
+                }
+                PathHandler::with_root(home)
+            }
+            None => PathHandler::new(),
+        };
+        self.quota = match self.config.users.get(&username) {
+            Some(user) => match self
+                .context
+                .quotas
+                .for_user(&*self.storage, &username, user)
+                .await
+            {
+                Ok(quota) => quota,
+                Err(e) => {
+                    log::error!("Failed to compute quota usage for user {}: {}", username, e);
+                    self.logged = false;
+                    return self
+                        .send_response(ACTION_ABORTED_LOCAL_ERROR, "Cannot compute quota usage")
+                        .await;
+                }
+            },
+            None => None,
+        };
+        self.user_rates = Some(self.context.bandwidth.for_user(&username));
+        self.trash = match (&self.config.trash_dir, &self.local) {
+            (Some(dir), Some(_)) => {
+                let trash = Trash::new(dir, &username, self.config.trash_retention);
+                trash.purge().await;
+                Some(trash)
+            }
+            _ => None,
+        };
+        self.update_trash_usage().await;
         self.logged = true;
+        self.emit(Event::LoggedIn);
         self.send_response(USER_LOGGED_IN, "logged in.").await
     }
+    // 登录时创建不存在的主目录
+    async fn create_home(&self, home: &Path) -> std::io::Result<()> {
+        let mut dir = PathBuf::from("/");
+        for component in home.components().skip(1) {
+            dir.push(component);
+            match self.storage.mkdir(&dir).await {
+                Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(e),
+                _ => {}
+            }
+        }
+        if !self.storage.metadata(home).await?.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Home is not a directory",
+            ));
+        }
+        Ok(())
+    }
     async fn acct(&mut self, _s: &str) -> std::io::Result<()> {
         self.send_response(SYNTAX_ERROR_UNRECOGNIZED_COMMAND, "Unsupported command")
             .await
@@ -144,9 +465,8 @@ impl Session {
                 .await?;
             return Ok(());
         }
-        match self.path_handler.cd(s) {
-            Ok(_) => {
-                let pwd = self.path_handler.get_pwd();
+        match self.change_dir(s).await {
+            Ok(pwd) => {
                 self.send_response(
                     FILE_ACTION_COMPLETED,
                     format!("Changed directory to {}", pwd.display()),
@@ -160,6 +480,25 @@ impl Session {
         }
     }
 
+    async fn change_dir(&mut self, s: &str) -> std::io::Result<PathBuf> {
+        let path = self.path_handler.resolve(s)?;
+        let metadata = self.storage.metadata(&path).await?;
+        if !self.is_browsable(&path, &metadata) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        self.path_handler.set_pwd(path);
+        Ok(self.path_handler.get_pwd())
+    }
+
+    // 目录或开启浏览时的压缩包，压缩包本身是文件，但可以进入浏览
+    fn is_browsable(&self, path: &Path, metadata: &Metadata) -> bool {
+        metadata.is_dir()
+            || (self.config.browse_archives && metadata.is_file() && path::is_archive(path))
+    }
+
     async fn pwd(&mut self, _s: &str) -> std::io::Result<()> {
         let pwd = self.path_handler.get_pwd();
         self.send_response(PATHNAME_CREATED, pwd.to_string_lossy())
@@ -167,289 +506,1003 @@ impl Session {
     }
     async fn pasv(&mut self, _s: &str) -> std::io::Result<()> {
         logged!(self);
-        let listener = TcpListener::bind(format!("{}:0", self.socket.local_addr()?.ip())).await?;
-        let addr = listener.local_addr()?;
-        self.data_listener = Some(listener);
-        self.data_port = None;
+        if self.epsv_all {
+            return self
+                .send_response(COMMANDS_BAD_SEQUENCE, "Only EPSV allowed after EPSV ALL")
+                .await;
+        }
+        let local_ip = self.socket.local_addr()?.ip();
+        // PASV回复只能表示IPv4地址，IPv6连接需要使用EPSV
+        let ip = match self.passive.advertised_ip(local_ip) {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(ip) => ip.to_ipv4_mapped(),
+        };
+        let Some(ip) = ip else {
+            return self
+                .send_response(
+                    ERROR_OPENING_DATA_CONNECTION,
+                    "Use EPSV for IPv6 connections",
+                )
+                .await;
+        };
+        let Some(port) = self.open_passive(local_ip).await? else {
+            return Ok(());
+        };
 
         // 构造PASV响应
-        let (ip, port) = (addr.ip(), addr.port());
+        let [a, b, c, d] = ip.octets();
         let (p1, p2) = (port >> 8, port & 0xFF);
-
-        let ip = ip.to_string();
-        let mut ip = ip.split('.');
-
         let response = format!(
             "Entering Passive Mode ({},{},{},{},{},{})",
-            ip.next().unwrap(),
-            ip.next().unwrap(),
-            ip.next().unwrap(),
-            ip.next().unwrap(),
-            p1,
-            p2
+            a, b, c, d, p1, p2
         );
         self.send_response(ENTERING_PASSIVE_MODE, response).await
     }
-    async fn nlst(&mut self, s: &str) -> std::io::Result<()> {
+    // RFC 2428，回复中只有端口，客户端使用控制连接的地址
+    async fn epsv(&mut self, args: &str) -> std::io::Result<()> {
         logged!(self);
-
-        let path = self.path_handler.to_server_path(s)?;
-
-        self.with_data_connection(|mut datasock| async move {
-            // 获取目录列表
-            let entries = Session::exec_list_dir_name(&path)?;
-            // 写入数据
-            datasock.write_all(entries.as_bytes()).await
-        })
+        let local_ip = self.socket.local_addr()?.ip().to_canonical();
+        let protocol = if local_ip.is_ipv4() { "1" } else { "2" };
+        match args.trim() {
+            "" => {}
+            s if s.eq_ignore_ascii_case("ALL") => {
+                self.epsv_all = true;
+                return self.send_response(COMMAND_OK, "EPSV ALL ok").await;
+            }
+            s if s == protocol => {}
+            _ => {
+                return self
+                    .send_response(
+                        NETWORK_PROTOCOL_NOT_SUPPORTED,
+                        format!("Network protocol not supported, use ({})", protocol),
+                    )
+                    .await;
+            }
+        }
+        let Some(port) = self.open_passive(local_ip).await? else {
+            return Ok(());
+        };
+        self.send_response(
+            ENTERING_EXTENDED_PASSIVE_MODE,
+            format!("Entering Extended Passive Mode (|||{}|)", port),
+        )
         .await
     }
-
-    fn exec_list_dir_name(path: &Path) -> std::io::Result<String> {
-        let mut entries = String::new();
-        for entry in std::fs::read_dir(path)? {
-            let entry = entry?;
-            if let Ok(file_type) = entry.file_type() {
-                if file_type.is_dir() {
-                    entries.push_str(&format!("{}/\n", entry.file_name().to_string_lossy()));
-                } else if file_type.is_file() {
-                    entries.push_str(&format!("{}\n", entry.file_name().to_string_lossy()));
-                } else {
-                    entries.push_str(&format!(
-                        "{} (symlink)\n",
-                        entry.file_name().to_string_lossy()
-                    ));
-                }
-            } else {
-                entries.push_str(&format!(
-                    "{} (error reading type)\n",
-                    entry.file_name().to_string_lossy()
-                ));
+    // 分配被动端口并开始监听，失败时已回复客户端并返回None
+    async fn open_passive(&mut self, local_ip: IpAddr) -> std::io::Result<Option<u16>> {
+        // 先关闭之前未使用的监听端口，使其可以被重新分配
+        self.data_listener = None;
+        self.data_port = None;
+        let listener = match self.passive.bind(local_ip) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::warn!("Failed to allocate passive port: {}", e);
+                self.send_response(ERROR_OPENING_DATA_CONNECTION, e.to_string())
+                    .await?;
+                return Ok(None);
+            }
+        };
+        let port = listener.local_addr()?.port();
+        self.data_listener = Some(listener);
+        Ok(Some(port))
+    }
+    async fn nlst(&mut self, s: &str) -> std::io::Result<()> {
+        logged!(self);
+        let entries = match self.list_dir(s).await {
+            Ok(entries) => entries,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let mut names = String::new();
+        for entry in &entries {
+            names.push_str(&entry.name);
+            match entry.metadata.kind {
+                FileKind::Dir => names.push_str("/\n"),
+                FileKind::Symlink => names.push_str(" (symlink)\n"),
+                FileKind::File => names.push('\n'),
             }
         }
-        Ok(if entries.is_empty() {
-            "No files found.\n".to_string()
-        } else {
-            entries
+        if names.is_empty() {
+            names.push_str("No files found.\n");
+        }
+        let (mode, level) = (self.transfer_mode, self.deflate_level);
+        self.with_data_connection(|datasock| async move {
+            let mut out = mode.writer(datasock, level, 0);
+            out.write_all(names.as_bytes()).await?;
+            out.shutdown().await
         })
+        .await?;
+        Ok(())
     }
 
     async fn list(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        let path = self.path_handler.to_server_path(s)?;
-        self.with_data_connection(|mut datasock| async move {
-            #[cfg(not(target_os = "windows"))]
-            let dirlist = Command::new("ls")
-                .arg("-all")
-                .arg(path)
-                .output()
-                .await?
-                .stdout;
-            #[cfg(target_os = "windows")]
-            let dirlist = Command::new("cmd")
-                .arg("/C")
-                .arg(format!("dir {}", path.display()))
-                .output()
-                .await?
-                .stdout;
-            datasock.write_all(&dirlist).await
+        let entries = match self.list_dir(s).await {
+            Ok(entries) => entries,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let now = SystemTime::now();
+        let lines: String = entries
+            .iter()
+            .map(|entry| listing::list_line(entry, now))
+            .collect();
+        let (mode, level) = (self.transfer_mode, self.deflate_level);
+        self.with_data_connection(|datasock| async move {
+            let mut out = mode.writer(datasock, level, 0);
+            out.write_all(lines.as_bytes()).await?;
+            out.shutdown().await
         })
-        .await
+        .await?;
+        Ok(())
+    }
+
+    // 列出目录内容，参数为文件时只返回该文件；忽略ls风格的选项如-a、-l
+    async fn list_dir(&self, s: &str) -> std::io::Result<Vec<DirEntry>> {
+        let s = s.split_whitespace().filter(|arg| !arg.starts_with('-'));
+        let path = self.path_handler.resolve(s.collect::<Vec<_>>().join(" "))?;
+        let metadata = self.storage.metadata(&path).await?;
+        if self.is_browsable(&path, &metadata) {
+            return self.storage.list(&path).await;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Ok(vec![DirEntry {
+            name: name.into_owned(),
+            metadata,
+        }])
     }
 
     async fn retr(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        match self.path_handler.to_server_path(s) {
-            Ok(file_path) => {
-                self.with_data_connection(|mut datasock| async move {
-                    let mut file = tokio::fs::File::open(file_path).await?;
-                    io::copy(&mut file, &mut datasock).await?;
-                    Ok(())
-                })
-                .await
+        let offset = std::mem::take(&mut self.restart_offset);
+        let path = match self.path_handler.resolve(s) {
+            Ok(path) => path,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        // 文件不存在时，尝试将同名目录打包下载，如RETR dir.tar
+        if let Err(e) = self.storage.metadata(&path).await
+            && e.kind() == io::ErrorKind::NotFound
+            && let Some((dir, format)) = ArchiveFormat::split(&path)
+            && self.storage.metadata(&dir).await.is_ok_and(|m| m.is_dir())
+        {
+            if offset > 0 {
+                return self
+                    .send_response(
+                        ACTION_NOT_TAKEN_INVALID_REST,
+                        "Restart is not supported for directory archives",
+                    )
+                    .await;
             }
-            Err(e) => {
-                return self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await;
+            let storage = self.storage.clone();
+            let (mode, level) = (self.transfer_mode, self.deflate_level);
+            let done = self
+                .with_data_connection(|datasock| async move {
+                    let out = mode.writer(datasock, level, 0);
+                    download::write_archive(&*storage, &dir, format, out).await
+                })
+                .await?;
+            if done {
+                self.emit(Event::Downloaded { path });
             }
+            return Ok(());
+        }
+        // ASCII模式下REST的位置是转换后的位置，需要从头读取
+        let ascii = self.transfer_type == TransferType::Ascii;
+        let (mode, level) = (self.transfer_mode, self.deflate_level);
+        let start = if ascii { 0 } else { offset };
+        // 打开文件失败时不建立数据连接
+        let mut file = match self.storage.open_read(&path, start).await {
+            Ok(file) => file,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let done = self
+            .with_data_connection(|datasock| async move {
+                let mut out = mode.writer(datasock, level, offset);
+                if ascii {
+                    ascii::copy_to_crlf(&mut file, &mut out, offset).await?;
+                } else {
+                    io::copy(&mut file, &mut out).await?;
+                }
+                out.shutdown().await
+            })
+            .await?;
+        if done {
+            self.emit(Event::Downloaded { path });
         }
+        Ok(())
     }
     async fn r#type(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        match s.to_uppercase().as_str() {
-            "A" => {
-                self.send_response(COMMAND_OK, "Switching to ASCII mode")
-                    .await
+        let s = s.to_uppercase();
+        let mut args = s.split_whitespace();
+        // 只支持非打印格式的ASCII和8位的本地类型
+        let (transfer_type, msg) = match (args.next(), args.next(), args.next()) {
+            (Some("A"), None | Some("N"), None) => (TransferType::Ascii, "Switching to ASCII mode"),
+            (Some("I"), None, None) | (Some("L"), Some("8"), None) => {
+                (TransferType::Binary, "Switching to Binary mode")
             }
-            "I" => {
-                self.send_response(COMMAND_OK, "Switching to Binary mode")
-                    .await
+            (Some("A" | "E" | "L"), _, _) => {
+                return self
+                    .send_response(COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER, "Unsupported type")
+                    .await;
             }
             _ => {
-                self.send_response(ACTION_NOT_TAKEN, "Unsupported type")
-                    .await
+                return self
+                    .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid TYPE command")
+                    .await;
             }
-        }
+        };
+        self.transfer_type = transfer_type;
+        self.send_response(COMMAND_OK, msg).await
     }
 
-    async fn stor(&mut self, s: &str) -> std::io::Result<()> {
+    async fn rest(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        let file_path = self.path_handler.non_canonicalized_path(s)?;
-        self.with_data_connection(|mut datasock| async move {
-            let mut file = tokio::fs::File::create(file_path).await?;
-            io::copy(&mut datasock, &mut file).await?;
-            Ok(())
-        })
-        .await
+        match s.trim().parse::<u64>() {
+            Ok(offset) => {
+                self.restart_offset = offset;
+                self.send_response(
+                    FILE_ACTION_NEEDS_FURTHER_INFO,
+                    format!("Restarting at {}", offset),
+                )
+                .await
+            }
+            Err(_) => {
+                self.send_response(SYNTAX_ERROR_PARAMETERS, "Invalid restart offset")
+                    .await
+            }
+        }
     }
 
-    async fn stru(&mut self, args: &str) -> std::io::Result<()> {
-        match args {
-            "F" => {
-                self.send_response(COMMAND_OK, "Structure set to File.")
-                    .await
+    // ASCII模式下返回转换行尾后实际传输的大小
+    async fn size(&mut self, s: &str) -> std::io::Result<()> {
+        logged!(self);
+        let result = async {
+            let path = self.path_handler.resolve(s)?;
+            let metadata = self.storage.metadata(&path).await?;
+            if !metadata.is_file() {
+                return Err(std::io::Error::other("Not a regular file"));
             }
-            "R" | "P" => {
-                self.send_response(COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER, "not supported")
-                    .await
+            // 转换后的大小需要读取整个文件，与vsftpd一样在ASCII模式下拒绝
+            match self.transfer_type {
+                TransferType::Binary => Ok(metadata.len),
+                TransferType::Ascii => Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "SIZE not allowed in ASCII mode",
+                )),
             }
-            _ => {
-                self.send_response(SYNTAX_ERROR_PARAMETERS, "SyntaxErrorParameters")
+        }
+        .await;
+        match result {
+            Ok(size) => self.send_response(FILE_STATUS, size.to_string()).await,
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    async fn mdtm(&mut self, s: &str) -> std::io::Result<()> {
+        logged!(self);
+        let result = async {
+            let path = self.path_handler.resolve(s)?;
+            self.storage
+                .metadata(&path)
+                .await?
+                .modified
+                .ok_or_else(|| std::io::Error::other("Modification time not available"))
+        }
+        .await;
+        match result {
+            Ok(time) => {
+                self.send_response(FILE_STATUS, listing::format_timeval(time))
                     .await
             }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
         }
     }
-    async fn get_data_socket(&mut self) -> std::io::Result<TcpStream> {
-        if let Some(socket) = self.data_port.take() {
-            return Ok(socket);
+
+    // 设置修改时间并回复实际设置的时间
+    async fn set_mtime(&mut self, s: &str, time: SystemTime) -> std::io::Result<()> {
+        let result = async {
+            let path = self.path_handler.resolve(s)?;
+            self.storage.set_mtime(&path, time).await
         }
-        if let Some(listener) = self.data_listener.take() {
-            let (data_socket, _) = listener.accept().await?;
-            return Ok(data_socket);
+        .await;
+        match result {
+            Ok(()) => {
+                let msg = format!("Modify={}; {}", listing::format_timeval(time), s);
+                self.send_response(FILE_STATUS, msg).await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
         }
-        self.send_response(
-            ERROR_OPENING_DATA_CONNECTION,
-            "Failed to open data connection",
-        )
-        .await?;
-        Err(std::io::Error::new(
-            std::io::ErrorKind::NotConnected,
-            "No data connection available",
-        ))
     }
-    async fn with_data_connection<F, Fut>(&mut self, operation: F) -> std::io::Result<()>
-    where
-        F: FnOnce(TcpStream) -> Fut,
-        Fut: Future<Output = std::io::Result<()>>,
-    {
-        // 获取数据连接所有权
-        let data_socket = self.get_data_socket().await?;
-
-        // 发送准备就绪响应
-        self.send_response(
-            FILE_STATUS_OK_OPENING_DATA_CONNECTION,
-            "Opening Data Connection",
-        )
-        .await?;
-
-        // 执行实际操作
-        let result = operation(data_socket).await;
 
-        // 无论成功与否，发送完成响应
-        self.send_response(CLOSING_DATA_CONNECTION, "Transfer complete")
-            .await?;
-
-        result
-    }
-    async fn dele(&mut self, args: &str) -> std::io::Result<()> {
+    // MFMT YYYYMMDDHHMMSS path，与SITE UTIME使用同一权限
+    async fn mfmt(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        match self.path_handler.to_server_path(args) {
-            Ok(path) => {
-                if let Err(e) = tokio::fs::remove_file(path).await {
-                    self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await
-                } else {
-                    self.send_response(FILE_ACTION_COMPLETED, "File deleted")
-                        .await
-                }
-            }
-            Err(e) => self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await,
+        if !self.site_allowed(SiteCommand::Utime) {
+            return self
+                .send_response(
+                    ACTION_NOT_TAKEN,
+                    "Changing modification times is not allowed",
+                )
+                .await;
         }
+        let Some((time, name)) = s
+            .split_once(' ')
+            .and_then(|(time, name)| Some((listing::parse_timeval(time)?, name)))
+        else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid time or path")
+                .await;
+        };
+        self.set_mtime(name, time).await
     }
-    async fn rmd(&mut self, args: &str) -> std::io::Result<()> {
+
+    // MFF fact=value;... path，只支持modify
+    async fn mff(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        match self.path_handler.to_server_path(args) {
-            Ok(path) => {
-                if let Err(e) = tokio::fs::remove_dir_all(path).await {
-                    self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await
-                } else {
-                    self.send_response(FILE_ACTION_COMPLETED, "deleted").await
+        if !self.site_allowed(SiteCommand::Utime) {
+            return self
+                .send_response(
+                    ACTION_NOT_TAKEN,
+                    "Changing modification times is not allowed",
+                )
+                .await;
+        }
+        let Some((facts, name)) = s.split_once(' ') else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid facts or path")
+                .await;
+        };
+        let mut modify = None;
+        for fact in facts.split(';').filter(|fact| !fact.is_empty()) {
+            match fact.split_once('=') {
+                Some((key, value)) if key.eq_ignore_ascii_case("modify") => {
+                    modify = listing::parse_timeval(value);
+                    if modify.is_none() {
+                        return self
+                            .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid modify fact")
+                            .await;
+                    }
+                }
+                Some(_) => {
+                    return self
+                        .send_response(
+                            COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER,
+                            format!("Unsupported fact {}", fact),
+                        )
+                        .await;
+                }
+                None => {
+                    return self
+                        .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid facts or path")
+                        .await;
                 }
             }
-            Err(e) => self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await,
         }
-    }
-    async fn mkd(&mut self, args: &str) -> std::io::Result<()> {
-        logged!(self);
-        let path = match self.path_handler.non_canonicalized_path(args) {
-            Ok(path) => path,
-            Err(e) => {
-                return self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await;
-            }
+        let Some(time) = modify else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "No facts given")
+                .await;
         };
-        if let Err(e) = tokio::fs::create_dir(path).await {
-            self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await
-        } else {
-            self.send_response(PATHNAME_CREATED, "directory created")
-                .await
+        self.set_mtime(name, time).await
+    }
+
+    // 计算文件摘要前检查路径是否为普通文件
+    async fn checksum(
+        &self,
+        path: &Path,
+        algorithm: HashAlgorithm,
+        start: u64,
+        end: Option<u64>,
+    ) -> std::io::Result<String> {
+        let metadata = self.storage.metadata(path).await?;
+        if !metadata.is_file() {
+            return Err(std::io::Error::other("Not a regular file"));
         }
+        checksum::checksum(&*self.storage, path, algorithm, start, end).await
     }
-    async fn rnfr(&mut self, args: &str) -> std::io::Result<()> {
+
+    async fn hash(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        match self.path_handler.to_server_path(args) {
-            Ok(path) => {
-                self.rename_from_path = Some(path);
-                self.send_response(FILE_ACTION_NEEDS_FURTHER_INFO, "Enter target name")
-                    .await
+        let range = self.hash_range.take();
+        let algorithm = self.hash_algorithm;
+        let result = async {
+            let path = self.path_handler.resolve(s)?;
+            let (start, end) = match range {
+                Some((start, Some(end))) => (start, end),
+                Some((start, None)) => (start, self.storage.metadata(&path).await?.len),
+                None => (0, self.storage.metadata(&path).await?.len),
+            };
+            self.checksum(&path, algorithm, start, Some(end))
+                .await
+                .map(|hash| (start, end, hash))
+        }
+        .await;
+        match result {
+            Ok((start, end, hash)) => {
+                let msg = format!(
+                    "{} {}-{} {} {}",
+                    algorithm.name(),
+                    start,
+                    end.saturating_sub(1),
+                    hash,
+                    s
+                );
+                self.send_response(FILE_STATUS, msg).await
             }
-            Err(e) => self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await,
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
         }
     }
 
-    async fn rnto(&mut self, args: &str) -> std::io::Result<()> {
+    // RANG start end，"RANG 1 0"取消已设置的范围
+    async fn rang(&mut self, s: &str) -> std::io::Result<()> {
         logged!(self);
-        let rename_from = match self.rename_from_path.take() {
-            Some(path) => path,
+        let range = s
+            .split_once(' ')
+            .and_then(|(start, end)| Some((start.parse::<u64>().ok()?, end.parse::<u64>().ok()?)));
+        match range {
+            Some((1, 0)) => {
+                self.hash_range = None;
+                self.send_response(FILE_ACTION_NEEDS_FURTHER_INFO, "Range reset")
+                    .await
+            }
+            Some((start, end)) if start <= end => {
+                // 结束位置为u64::MAX时表示到文件末尾
+                self.hash_range = Some((start, end.checked_add(1)));
+                self.send_response(
+                    FILE_ACTION_NEEDS_FURTHER_INFO,
+                    format!("Restarting at {}. Ending byte {}", start, end),
+                )
+                .await
+            }
+            _ => {
+                self.send_response(SYNTAX_ERROR_PARAMETERS, "Invalid range")
+                    .await
+            }
+        }
+    }
+
+    // XCRC/XMD5/XSHA1/XSHA256 path [start [end]]，带范围时路径需要加引号
+    async fn xhash(&mut self, s: &str, algorithm: HashAlgorithm) -> std::io::Result<()> {
+        logged!(self);
+        let Some((name, start, end)) = checksum::parse_x_args(s) else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid arguments")
+                .await;
+        };
+        let result = async {
+            let path = self.path_handler.resolve(name)?;
+            self.checksum(&path, algorithm, start, end).await
+        }
+        .await;
+        match result {
+            Ok(hash) => self.send_response(FILE_STATUS, hash).await,
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    async fn stor(&mut self, s: &str) -> std::io::Result<()> {
+        logged!(self);
+        self.upload(s, false).await
+    }
+
+    async fn appe(&mut self, s: &str) -> std::io::Result<()> {
+        logged!(self);
+        self.upload(s, true).await
+    }
+
+    async fn upload(&mut self, s: &str, mut append: bool) -> std::io::Result<()> {
+        let offset = std::mem::take(&mut self.restart_offset);
+        let path = match self.path_handler.resolve(s) {
+            Ok(path) => path,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let ascii = self.transfer_type == TransferType::Ascii;
+        let mode = self.transfer_mode;
+        // 只支持继续之前中止的上传，优先续传保留的部分，其次从目标文件末尾追加
+        let mut resume = false;
+        if offset > 0 {
+            let partial = self.storage.partial_len(&path).await.unwrap_or_default();
+            let len = match self.storage.metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => Some(metadata.len),
+                _ => None,
+            };
+            if ascii || (partial != Some(offset) && len != Some(offset)) {
+                return self
+                    .send_response(
+                        ACTION_NOT_TAKEN_INVALID_REST,
+                        "Restart offset must match the current file size in binary mode",
+                    )
+                    .await;
+            }
+            resume = partial == Some(offset);
+            append = !resume;
+        }
+        let existing = match self.storage.metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => Some(metadata.len),
+            _ => None,
+        };
+        // 超出配额时不建立数据连接
+        let mut reservation = None;
+        if let Some(quota) = &self.quota {
+            let reserved = quota
+                .reserve(&path, existing, append)
+                .and_then(|mut reservation| {
+                    // 续传的部分替换目标文件后才计入使用情况
+                    if resume {
+                        reservation.resume(offset)?;
+                    }
+                    Ok(reservation)
+                });
+            reservation = match reserved {
+                Ok(reservation) => Some(reservation),
+                Err(e) => {
+                    return self
+                        .send_response(ACTION_NOT_TAKEN_INSUFFICIENT_STORAGE_SPACE, e.to_string())
+                        .await;
+                }
+            };
+        }
+        let opened = match resume {
+            true => self.storage.resume_write(&path).await,
+            false => self.storage.open_write(&path, append).await,
+        };
+        let mut file: WriteStream = match opened {
+            Ok(file) => match reservation {
+                Some(reservation) => Box::new(QuotaWriter::new(file, reservation)),
+                None => file,
+            },
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let done = self
+            .with_data_connection(|datasock| async move {
+                let mut input = mode.reader(datasock);
+                if ascii {
+                    ascii::copy_from_crlf(&mut input, &mut file).await?;
+                } else {
+                    io::copy(&mut input, &mut file).await?;
+                }
+                file.shutdown().await
+            })
+            .await?;
+        // 传输中止时可能留下部分数据，按实际大小更新使用情况
+        let after = self.usage(&path).await;
+        self.update_quota(&path, Usage::file(existing), after);
+        if done {
+            if existing.is_none() {
+                self.apply_umask(&path, 0o666).await;
+            }
+            self.emit(Event::Uploaded { path });
+        }
+        Ok(())
+    }
+
+    // 按会话的umask设置新建文件或目录的权限，存储后端不支持权限时忽略
+    async fn apply_umask(&self, path: &Path, mode: u32) {
+        if let Err(e) = self.storage.set_permissions(path, mode & !self.umask).await
+            && e.kind() != io::ErrorKind::Unsupported
+        {
+            log::warn!("Failed to set permissions of {}: {}", path.display(), e);
+        }
+    }
+
+    // 有用户设置了配额时统计路径下的占用，否则不需要遍历
+    // 没有配额的用户的修改同样计入其他用户的配额
+    async fn usage(&self, path: &Path) -> Usage {
+        if self.context.quotas.is_empty() {
+            return Usage::default();
+        }
+        quota::usage(&*self.storage, path)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("Failed to compute usage of {}: {}", path.display(), e);
+                Usage::default()
+            })
+    }
+
+    fn update_quota(&self, path: &Path, before: Usage, after: Usage) {
+        self.context.quotas.update(path, before, after);
+    }
+
+    async fn stru(&mut self, args: &str) -> std::io::Result<()> {
+        match args {
+            "F" => {
+                self.send_response(COMMAND_OK, "Structure set to File.")
+                    .await
+            }
+            "R" | "P" => {
+                self.send_response(COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER, "not supported")
+                    .await
+            }
+            _ => {
+                self.send_response(SYNTAX_ERROR_PARAMETERS, "SyntaxErrorParameters")
+                    .await
+            }
+        }
+    }
+    async fn get_data_socket(&mut self) -> std::io::Result<TcpStream> {
+        if let Some(addr) = self.data_port.take() {
+            return match tokio::time::timeout(
+                self.config.data_connect_timeout,
+                TcpStream::connect(addr),
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(_) => Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timed out connecting to data port",
+                )),
+            };
+        }
+        if let Some(listener) = self.data_listener.take() {
+            let peer_ip = self.socket.peer_addr()?.ip().to_canonical();
+            let promiscuous = self.config.pasv_promiscuous;
+            let accept = Self::accept_from(&listener, peer_ip, promiscuous);
+            // 超时或出错后监听端口随listener一起关闭
+            return match tokio::time::timeout(self.config.data_accept_timeout, accept).await {
+                Ok(res) => res,
+                Err(_) => Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timed out waiting for data connection",
+                )),
+            };
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotConnected,
+            "No data connection available",
+        ))
+    }
+    // 只接受来自控制连接同一IP的数据连接，防止被动端口被他人抢占
+    async fn accept_from(
+        listener: &TcpListener,
+        peer_ip: IpAddr,
+        promiscuous: bool,
+    ) -> std::io::Result<TcpStream> {
+        loop {
+            let (data_socket, addr) = listener.accept().await?;
+            if promiscuous || Self::same_host(addr.ip(), peer_ip) {
+                return Ok(data_socket);
+            }
+            log::warn!(
+                "Rejected data connection from {}, expected peer {}",
+                addr,
+                peer_ip
+            );
+        }
+    }
+    fn same_host(a: IpAddr, b: IpAddr) -> bool {
+        a.to_canonical() == b.to_canonical()
+    }
+    // 返回传输是否成功完成
+    async fn with_data_connection<F, Fut>(&mut self, operation: F) -> std::io::Result<bool>
+    where
+        F: FnOnce(DataStream) -> Fut,
+        Fut: Future<Output = std::io::Result<()>>,
+    {
+        // 获取数据连接所有权
+        let data_socket = match self.get_data_socket().await {
+            Ok(socket) => DataStream::new(socket, self.config.data_idle_timeout)
+                .throttle(self.rates.iter().chain(&self.user_rates).cloned().collect()),
+            Err(e) => {
+                log::debug!("Failed to open data connection: {}", e);
+                self.send_response(
+                    ERROR_OPENING_DATA_CONNECTION,
+                    format!("Can't open data connection: {}", e),
+                )
+                .await?;
+                return Ok(false);
+            }
+        };
+
+        // 发送准备就绪响应
+        self.send_response(
+            FILE_STATUS_OK_OPENING_DATA_CONNECTION,
+            "Opening Data Connection",
+        )
+        .await?;
+
+        // 执行实际操作，会话到期时中止传输
+        // 服务器关闭时允许传输在drain_timeout内完成，超时后中止
+        let deadline = self.deadline();
+        let drain_timeout = self.config.drain_timeout;
+        let shutdown = &mut self.shutdown;
+        let drain = async move {
+            let _ = shutdown.recv().await;
+            tokio::time::sleep(drain_timeout).await;
+        };
+        let revoked = Self::wait_revoked(
+            &mut self.config_updates,
+            self.username.as_deref().filter(|_| self.logged),
+        );
+        let result = tokio::select! {
+            res = operation(data_socket) => res,
+            _ = Self::sleep_until(deadline) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Maximum session time exceeded",
+            )),
+            _ = drain => Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "Service shutting down",
+            )),
+            _ = revoked => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Account removed",
+            )),
+        };
+        match result {
+            Ok(()) => {
+                self.send_response(CLOSING_DATA_CONNECTION, "Transfer complete")
+                    .await?;
+                Ok(true)
+            }
+            Err(e) => {
+                log::debug!("Transfer failed: {}", e);
+                // 超出配额或存储空间不足
+                let code = if e.kind() == std::io::ErrorKind::StorageFull {
+                    FILE_ACTION_ABORTED
+                } else {
+                    TRANSFER_ABORTED
+                };
+                self.send_response(code, format!("Transfer aborted: {}", e))
+                    .await?;
+                Ok(false)
+            }
+        }
+    }
+    async fn dele(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        let path = match self.path_handler.resolve(args) {
+            Ok(path) => path,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let before = self.usage(&path).await;
+        match self.remove(&path, false).await {
+            Ok(()) => {
+                self.update_quota(&path, before, Usage::default());
+                self.emit(Event::Deleted { path });
+                self.send_response(FILE_ACTION_COMPLETED, "File deleted")
+                    .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+    async fn rmd(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        let path = match self.path_handler.resolve(args) {
+            Ok(path) => path,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        let before = self.usage(&path).await;
+        match self.remove(&path, true).await {
+            Ok(()) => {
+                self.update_quota(&path, before, Usage::default());
+                self.emit(Event::DirectoryRemoved { path });
+                self.send_response(FILE_ACTION_COMPLETED, "deleted").await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+    // 设置了回收站时移入回收站，否则直接删除，dir表示删除目录及其内容
+    async fn remove(&self, path: &Path, dir: bool) -> std::io::Result<()> {
+        let (Some(trash), Some(local)) = (&self.trash, &self.local) else {
+            return if dir {
+                self.storage.remove_dir_all(path).await
+            } else {
+                self.storage.remove_file(path).await
+            };
+        };
+        let metadata = self.storage.metadata(path).await?;
+        if dir && !metadata.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        if !dir && metadata.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        if path.parent().is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Cannot remove the root directory",
+            ));
+        }
+        trash
+            .put(local.real_path(path)?, path.to_path_buf())
+            .await?;
+        self.update_trash_usage().await;
+        Ok(())
+    }
+
+    // 回收站中的内容计入用户的配额，回收站变化后重新统计
+    async fn update_trash_usage(&self) {
+        let (Some(trash), Some(quota)) = (&self.trash, &self.quota) else {
+            return;
+        };
+        match trash.usage().await {
+            Ok(items) => quota.set_trash(items),
+            Err(e) => log::warn!("Failed to compute recycle bin usage: {}", e),
+        }
+    }
+
+    async fn mkd(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        let result = match self.path_handler.resolve(args) {
+            Ok(path) => self.storage.mkdir(&path).await.map(|_| path),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(path) => {
+                self.apply_umask(&path, 0o777).await;
+                self.emit(Event::DirectoryCreated { path });
+                self.send_response(PATHNAME_CREATED, "directory created")
+                    .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+    async fn rnfr(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        let result = match self.path_handler.resolve(args) {
+            Ok(path) => self.storage.metadata(&path).await.map(|_| path),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(path) => {
+                self.rename_from_path = Some(path);
+                self.send_response(FILE_ACTION_NEEDS_FURTHER_INFO, "Enter target name")
+                    .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    async fn rnto(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        let rename_from = match self.rename_from_path.take() {
+            Some(path) => path,
             None => {
                 return self
                     .send_response(COMMANDS_BAD_SEQUENCE, "Please specify target file first")
                     .await;
             }
         };
-        let mut rename_to = self.path_handler.non_canonicalized_path(args)?;
+        let mut rename_to = match self.path_handler.resolve(args) {
+            Ok(path) => path,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
         mydbg!((&rename_from, &rename_to));
-        match (rename_from.is_dir(), rename_to.is_dir()) {
-            (false, true) => {
-                // 文件->路径
-                let filename = rename_from.file_name().unwrap();
-                rename_to.push(filename);
-            }
-            _ => {} // 同为文件或路径
+        let is_dir =
+            |meta: std::io::Result<crate::storage::Metadata>| meta.is_ok_and(|m| m.is_dir());
+        let from_dir = is_dir(self.storage.metadata(&rename_from).await);
+        let to_dir = is_dir(self.storage.metadata(&rename_to).await);
+        if let (false, true, Some(filename)) = (from_dir, to_dir, rename_from.file_name()) {
+            // 文件->路径
+            rename_to.push(filename);
         }
-        match std::fs::rename(rename_from, rename_to) {
-            Ok(()) => self.send_response(FILE_ACTION_COMPLETED, "Ok").await,
-            Err(e) => self.send_response(ACTION_NOT_TAKEN, &e.to_string()).await,
+        let moved = self.usage(&rename_from).await;
+        let replaced = self.usage(&rename_to).await;
+        let overwrite = self.config.rename_overwrite;
+        match self
+            .storage
+            .rename(&rename_from, &rename_to, overwrite)
+            .await
+        {
+            Ok(()) => {
+                self.update_quota(&rename_from, moved, Usage::default());
+                self.update_quota(&rename_to, replaced, moved);
+                self.emit(Event::Renamed {
+                    from: rename_from,
+                    to: rename_to,
+                });
+                self.send_response(FILE_ACTION_COMPLETED, "Ok").await
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                self.send_response(
+                    ACTION_NOT_TAKEN_FILENAME_NOT_ALLOWED,
+                    "Target already exists",
+                )
+                .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
         }
     }
 
     async fn opts(&mut self, args: &str) -> std::io::Result<()> {
-        match args {
-            "UTF8 ON" => self.send_response(COMMAND_OK, "UTF8 mode enabled").await,
+        let args = args.to_uppercase();
+        match args.split_whitespace().collect::<Vec<_>>()[..] {
+            ["UTF8", "ON"] => self.send_response(COMMAND_OK, "UTF8 mode enabled").await,
+            ["HASH"] => {
+                let name = self.hash_algorithm.name();
+                self.send_response(COMMAND_OK, name).await
+            }
+            ["HASH", name] => match HashAlgorithm::parse(name) {
+                Some(algorithm) => {
+                    self.hash_algorithm = algorithm;
+                    self.send_response(COMMAND_OK, algorithm.name()).await
+                }
+                None => {
+                    self.send_response(SYNTAX_ERROR_PARAMETERS, "Unknown hash algorithm")
+                        .await
+                }
+            },
+            ["MODE", "Z", "LEVEL", level] if self.config.mode_z => match level.parse() {
+                Ok(level @ 0..=9) => {
+                    self.deflate_level = level;
+                    self.send_response(COMMAND_OK, format!("MODE Z LEVEL set to {}", level))
+                        .await
+                }
+                _ => {
+                    self.send_response(SYNTAX_ERROR_PARAMETERS, "Level must be between 0 and 9")
+                        .await
+                }
+            },
             _ => {
                 self.send_response(SYNTAX_ERROR_PARAMETERS, "Unsupported OPTS command")
                     .await
             }
         }
     }
+
+    async fn mode(&mut self, args: &str) -> std::io::Result<()> {
+        let (mode, msg) = match args.to_uppercase().as_str() {
+            "S" => (TransferMode::Stream, "Mode set to Stream"),
+            "B" => (TransferMode::Block, "Mode set to Block"),
+            "Z" if self.config.mode_z => (TransferMode::Deflate, "Mode set to Deflate"),
+            "C" | "Z" => {
+                return self
+                    .send_response(COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER, "Unsupported mode")
+                    .await;
+            }
+            _ => {
+                return self
+                    .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid MODE command")
+                    .await;
+            }
+        };
+        self.transfer_mode = mode;
+        self.send_response(COMMAND_OK, msg).await
+    }
+
+    async fn feat(&mut self, _args: &str) -> std::io::Result<()> {
+        let mut lines = vec!["Features:".to_string()];
+        for feature in ["EPRT", "EPSV", "PASV", "REST STREAM", "SIZE", "UTF8"] {
+            lines.push(feature.to_string());
+        }
+        if self.config.mode_z {
+            lines.push("MODE Z".to_string());
+        }
+        // 当前选择的算法标记为*
+        let algorithms: Vec<_> = HashAlgorithm::ALL
+            .iter()
+            .map(|&algorithm| {
+                let mark = if algorithm == self.hash_algorithm {
+                    "*"
+                } else {
+                    ""
+                };
+                format!("{}{}", algorithm.name(), mark)
+            })
+            .collect();
+        lines.push(format!("HASH {}", algorithms.join(";")));
+        for command in ["MDTM", "MFMT", "MFF modify;"] {
+            lines.push(command.to_string());
+        }
+        for command in ["XCRC", "XMD5", "XSHA1", "XSHA256"] {
+            lines.push(command.to_string());
+        }
+        lines.push("End".to_string());
+        self.send_multiline(REPLY_SYSTEM_STATUS, &lines).await
+    }
     async fn port(&mut self, args: &str) -> std::io::Result<()> {
         logged!(self);
+        if self.epsv_all {
+            return self
+                .send_response(COMMANDS_BAD_SEQUENCE, "Only EPSV allowed after EPSV ALL")
+                .await;
+        }
         let parts: Vec<&str> = args.split(',').collect();
         if parts.len() != 6 {
             return self
@@ -461,18 +1514,470 @@ impl Session {
         let p2: u16 = parts[5].parse().unwrap_or(0);
         let port = (p1 << 8) | p2;
 
-        let addr = format!("{}:{}", ip, port);
-        self.data_port = Some(TcpStream::connect(addr).await?);
-        log::debug!("Connected to data port: {:?}", &self.data_port);
-
-        // 发送准备就绪响应
-        self.send_response(FILE_STATUS_OK_OPENING_DATA_CONNECTION, "File Status Ok")
-            .await?;
+        let addr = match format!("{}:{}", ip, port).parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => {
+                return self
+                    .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid PORT command")
+                    .await;
+            }
+        };
+        self.set_data_port(addr);
+        self.send_response(COMMAND_OK, "PORT command successful")
+            .await
+    }
+    // RFC 2428，参数形如|1|132.235.1.2|6275|或|2|::1|6275|
+    async fn eprt(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        if self.epsv_all {
+            return self
+                .send_response(COMMANDS_BAD_SEQUENCE, "Only EPSV allowed after EPSV ALL")
+                .await;
+        }
+        let args = args.trim();
+        let Some(delim) = args.chars().next() else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid EPRT command")
+                .await;
+        };
+        let parts: Vec<&str> = args.split(delim).collect();
+        if parts.len() != 5 || !parts[0].is_empty() || !parts[4].is_empty() {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid EPRT command")
+                .await;
+        }
+        let ip = match (parts[1], parts[2].parse::<IpAddr>()) {
+            ("1", Ok(ip @ IpAddr::V4(_))) | ("2", Ok(ip @ IpAddr::V6(_))) => ip,
+            ("1" | "2", _) => {
+                return self
+                    .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid EPRT address")
+                    .await;
+            }
+            _ => {
+                return self
+                    .send_response(
+                        NETWORK_PROTOCOL_NOT_SUPPORTED,
+                        "Network protocol not supported, use (1,2)",
+                    )
+                    .await;
+            }
+        };
+        let Ok(port) = parts[3].parse::<u16>() else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid EPRT port")
+                .await;
+        };
+        self.set_data_port(SocketAddr::new(ip, port));
+        self.send_response(COMMAND_OK, "EPRT command successful")
+            .await
+    }
+    fn set_data_port(&mut self, addr: SocketAddr) {
+        // 数据连接在传输开始时才建立
+        self.data_port = Some(addr);
+        log::debug!("Data port set to {}", addr);
 
         // 设置数据监听器为None，表示使用PORT模式
         self.data_listener = None;
+    }
+    async fn site(&mut self, args: &str) -> std::io::Result<()> {
+        logged!(self);
+        let (cmd, args) = args.split_once(' ').unwrap_or((args, ""));
+        let Some(command) = SiteCommand::parse(cmd) else {
+            return self
+                .send_response(
+                    COMMAND_NOT_IMPLEMENTED_FOR_PARAMETER,
+                    "Unknown SITE command",
+                )
+                .await;
+        };
+        if !self.site_allowed(command) {
+            return self
+                .send_response(
+                    ACTION_NOT_TAKEN,
+                    format!("SITE {} not allowed", command.name()),
+                )
+                .await;
+        }
+        match command {
+            SiteCommand::Help => self.site_help().await,
+            SiteCommand::Chmod => self.site_chmod(args).await,
+            SiteCommand::Umask => self.site_umask(args).await,
+            SiteCommand::Idle => self.site_idle(args).await,
+            SiteCommand::Utime => self.site_utime(args).await,
+            SiteCommand::Cpfr => self.site_cpfr(args).await,
+            SiteCommand::Cpto => self.site_cpto(args).await,
+            SiteCommand::Undelete => self.site_undelete(args).await,
+            SiteCommand::Quota => self.site_quota().await,
+        }
+    }
 
-        // 执行实际操作
-        Ok(())
+    // 用户数据库中未限制SITE子命令的用户可以使用全部子命令
+    fn site_allowed(&self, command: SiteCommand) -> bool {
+        let allowed = self
+            .username
+            .as_ref()
+            .and_then(|name| self.config.users.get(name))
+            .and_then(|user| user.site_commands.as_ref());
+        match allowed {
+            _ if command == SiteCommand::Help => true,
+            Some(allowed) => allowed
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(command.name())),
+            None => command.allowed_by_default(),
+        }
+    }
+
+    async fn site_help(&mut self) -> std::io::Result<()> {
+        let mut lines = vec!["The following SITE commands are available:".to_string()];
+        for command in SiteCommand::ALL {
+            if self.site_allowed(command) {
+                lines.push(command.usage().to_string());
+            }
+        }
+        lines.push("End".to_string());
+        self.send_multiline(HELP_MESSAGE, &lines).await
+    }
+
+    // SITE CHMOD mode path
+    async fn site_chmod(&mut self, args: &str) -> std::io::Result<()> {
+        let Some((mode, name)) = args
+            .split_once(' ')
+            .and_then(|(mode, name)| Some((site::parse_mode(mode)?, name)))
+        else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid mode or path")
+                .await;
+        };
+        let result = async {
+            let path = self.path_handler.resolve(name)?;
+            self.storage.set_permissions(&path, mode).await
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                self.send_response(COMMAND_OK, "SITE CHMOD command successful")
+                    .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    // 不带参数时显示当前值
+    async fn site_umask(&mut self, args: &str) -> std::io::Result<()> {
+        if args.is_empty() {
+            let msg = format!("Current UMASK is {:03o}", self.umask);
+            return self.send_response(COMMAND_OK, msg).await;
+        }
+        match config::parse_umask(args) {
+            Ok(umask) => {
+                self.umask = umask;
+                self.send_response(COMMAND_OK, format!("UMASK set to {:03o}", umask))
+                    .await
+            }
+            Err(_) => {
+                self.send_response(SYNTAX_ERROR_PARAMETERS, "Invalid UMASK")
+                    .await
+            }
+        }
+    }
+
+    async fn site_idle(&mut self, args: &str) -> std::io::Result<()> {
+        if args.is_empty() {
+            let msg = format!(
+                "Current idle timeout is {} seconds",
+                self.idle_timeout.as_secs()
+            );
+            return self.send_response(COMMAND_OK, msg).await;
+        }
+        // 只能缩短管理员配置的空闲时间
+        let max = self.config.idle_timeout;
+        match args.parse().map(Duration::from_secs) {
+            Ok(timeout) if !timeout.is_zero() && timeout <= max => {
+                self.idle_timeout = timeout;
+                let msg = format!("Idle timeout set to {} seconds", timeout.as_secs());
+                self.send_response(COMMAND_OK, msg).await
+            }
+            _ => {
+                let msg = format!(
+                    "Idle timeout must be between 1 and {} seconds",
+                    max.as_secs()
+                );
+                self.send_response(SYNTAX_ERROR_PARAMETERS, msg).await
+            }
+        }
+    }
+
+    // 与RNFR/RNTO相同，先记录源文件，由CPTO完成复制
+    async fn site_cpfr(&mut self, args: &str) -> std::io::Result<()> {
+        let result = async {
+            let path = self.path_handler.resolve(args)?;
+            if self.storage.metadata(&path).await?.is_dir() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::IsADirectory,
+                    "Cannot copy a directory",
+                ));
+            }
+            Ok(path)
+        }
+        .await;
+        match result {
+            Ok(path) => {
+                self.copy_from_path = Some(path);
+                self.send_response(
+                    FILE_ACTION_NEEDS_FURTHER_INFO,
+                    "File exists, ready for destination name",
+                )
+                .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    async fn site_cpto(&mut self, args: &str) -> std::io::Result<()> {
+        let Some(copy_from) = self.copy_from_path.take() else {
+            return self
+                .send_response(COMMANDS_BAD_SEQUENCE, "Need SITE CPFR first")
+                .await;
+        };
+        let mut copy_to = match self.path_handler.resolve(args) {
+            Ok(path) => path,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        // 目标为目录时复制到目录下
+        if let Ok(metadata) = self.storage.metadata(&copy_to).await
+            && metadata.is_dir()
+            && let Some(filename) = copy_from.file_name()
+        {
+            copy_to.push(filename);
+        }
+        let copied = self.usage(&copy_from).await;
+        let replaced = self.usage(&copy_to).await;
+        if let Some(quota) = &self.quota {
+            let existing = (replaced.files > 0).then_some(replaced.bytes);
+            let msg = match quota.upload_limit(&copy_to, existing, false) {
+                Ok(Some(limit)) if limit < copied.bytes => Some("Disk quota exceeded".to_string()),
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(msg) = msg {
+                return self
+                    .send_response(ACTION_NOT_TAKEN_INSUFFICIENT_STORAGE_SPACE, msg)
+                    .await;
+            }
+        }
+        // 与RNTO相同，只有设置了rename_overwrite才覆盖已存在的目标
+        let overwrite = self.config.rename_overwrite;
+        match self.storage.copy(&copy_from, &copy_to, overwrite).await {
+            Ok(()) => {
+                self.update_quota(&copy_to, replaced, copied);
+                self.emit(Event::Copied {
+                    from: copy_from,
+                    to: copy_to,
+                });
+                self.send_response(FILE_ACTION_COMPLETED, "Copy successful")
+                    .await
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                self.send_response(
+                    ACTION_NOT_TAKEN_FILENAME_NOT_ALLOWED,
+                    "Target already exists",
+                )
+                .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    // 不带参数时列出回收站内容，否则按id或原路径恢复，同一路径有多项时恢复最近删除的
+    async fn site_undelete(&mut self, args: &str) -> std::io::Result<()> {
+        let (Some(trash), Some(local)) = (self.trash.clone(), self.local.clone()) else {
+            return self
+                .send_response(ACTION_NOT_TAKEN, "Recycle bin is not enabled")
+                .await;
+        };
+        let mut entries = match trash.list().await {
+            Ok(entries) => entries,
+            Err(e) => return self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        };
+        // 主目录改变后，原主目录中删除的项不能恢复到新主目录之外
+        entries.retain(|entry| self.path_handler.contains(&entry.path));
+        // 列出时会清除过期的项
+        self.update_trash_usage().await;
+        if args.is_empty() {
+            if entries.is_empty() {
+                return self
+                    .send_response(REPLY_SYSTEM_STATUS, "Recycle bin is empty")
+                    .await;
+            }
+            let mut lines = vec!["Deleted items:".to_string()];
+            for entry in &entries {
+                let (year, month, day, hour, minute, second) = listing::civil_time(entry.deleted);
+                lines.push(format!(
+                    "{} {:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+                    entry.id,
+                    year,
+                    month,
+                    day,
+                    hour,
+                    minute,
+                    second,
+                    self.path_handler.client_path(&entry.path).display()
+                ));
+            }
+            lines.push("End".to_string());
+            return self.send_multiline(REPLY_SYSTEM_STATUS, &lines).await;
+        }
+        let path = self.path_handler.resolve(args).ok();
+        let Some(entry) = entries
+            .into_iter()
+            .find(|entry| entry.id == args || Some(&entry.path) == path.as_ref())
+        else {
+            return self
+                .send_response(ACTION_NOT_TAKEN, "No such item in recycle bin")
+                .await;
+        };
+        if let Some(quota) = &self.quota
+            && let Err(e) = quota.restore_allowed(&entry.path)
+        {
+            return self
+                .send_response(ACTION_NOT_TAKEN_INSUFFICIENT_STORAGE_SPACE, e.to_string())
+                .await;
+        }
+        let result = async {
+            let real = local.real_path(&entry.path)?;
+            trash.restore(&entry.id, real).await
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                let after = self.usage(&entry.path).await;
+                self.update_quota(&entry.path, Usage::default(), after);
+                self.update_trash_usage().await;
+                self.send_response(
+                    FILE_ACTION_COMPLETED,
+                    format!(
+                        "Restored {}",
+                        self.path_handler.client_path(&entry.path).display()
+                    ),
+                )
+                .await
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                self.send_response(ACTION_NOT_TAKEN_FILENAME_NOT_ALLOWED, e.to_string())
+                    .await
+            }
+            Err(e) => self.send_response(ACTION_NOT_TAKEN, e.to_string()).await,
+        }
+    }
+
+    async fn site_quota(&mut self) -> std::io::Result<()> {
+        let Some(quota) = &self.quota else {
+            return self
+                .send_response(REPLY_SYSTEM_STATUS, "No quota set for this user")
+                .await;
+        };
+        let mut lines = vec!["Quota usage:".to_string()];
+        lines.extend(quota.report());
+        lines.push("End".to_string());
+        self.send_multiline(REPLY_SYSTEM_STATUS, &lines).await
+    }
+
+    // SITE UTIME YYYYMMDDhhmm[ss] path，或SITE UTIME path atime mtime ctime UTC
+    async fn site_utime(&mut self, args: &str) -> std::io::Result<()> {
+        let words: Vec<_> = args.split(' ').collect();
+        let parsed = if words.len() >= 5 && words[words.len() - 1].eq_ignore_ascii_case("UTC") {
+            let name = words[..words.len() - 4].join(" ");
+            listing::parse_timeval(words[words.len() - 3]).map(|time| (time, name))
+        } else {
+            args.split_once(' ').and_then(|(time, name)| {
+                // 省略秒时补0
+                let time = if time.len() == 12 {
+                    format!("{}00", time)
+                } else {
+                    time.to_string()
+                };
+                Some((listing::parse_timeval(&time)?, name.to_string()))
+            })
+        };
+        let Some((time, name)) = parsed else {
+            return self
+                .send_response(SYNTAX_ERROR_PARAMETERS, "Invalid time or path")
+                .await;
+        };
+        self.set_mtime(&name, time).await
+    }
+
+    async fn rein(&mut self, _args: &str) -> std::io::Result<()> {
+        // 重置会话状态，关闭未使用的数据连接
+        self.logged = false;
+        self.username = None;
+        self.max_session = self.config.max_session;
+        self.data_listener = None;
+        self.data_port = None;
+        self.epsv_all = false;
+        self.rename_from_path = None;
+        self.copy_from_path = None;
+        self.path_handler = PathHandler::new();
+        self.quota = None;
+        self.trash = None;
+        self.user_rates = None;
+        self.transfer_type = TransferType::Binary;
+        self.transfer_mode = TransferMode::Stream;
+        self.deflate_level = mode::DEFAULT_LEVEL;
+        self.restart_offset = 0;
+        self.hash_algorithm = HashAlgorithm::Sha256;
+        self.hash_range = None;
+        self.umask = self.config.umask;
+        self.idle_timeout = self.config.idle_timeout;
+        self.send_response(SERVICE_READY_FOR_NEW_USER, "Service ready for new user")
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpSocket;
+
+    use super::*;
+
+    // 从指定的本地地址连接
+    async fn connect_from(ip: [u8; 4], port: u16) -> TcpStream {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind((ip, 0).into()).unwrap();
+        socket.connect(([127, 0, 0, 1], port).into()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_accept_from() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer_ip = IpAddr::from([127, 0, 0, 1]);
+
+        // 来自其他地址的连接被关闭，之后来自控制连接地址的连接被接受
+        let mut intruder = connect_from([127, 0, 0, 2], port).await;
+        let client = connect_from([127, 0, 0, 1], port).await;
+        let data = Session::accept_from(&listener, peer_ip, false)
+            .await
+            .unwrap();
+        assert_eq!(data.peer_addr().unwrap(), client.local_addr().unwrap());
+        let mut buf = Vec::new();
+        assert!(
+            intruder
+                .read_to_end(&mut buf)
+                .await
+                .map_or(true, |n| n == 0)
+        );
+
+        // promiscuous时接受任意地址
+        let intruder = connect_from([127, 0, 0, 2], port).await;
+        let data = Session::accept_from(&listener, peer_ip, true)
+            .await
+            .unwrap();
+        assert_eq!(data.peer_addr().unwrap(), intruder.local_addr().unwrap());
+
+        // IPv4映射的IPv6地址与IPv4地址视为同一主机
+        let mapped = IpAddr::from([0, 0, 0, 0, 0, 0xffff, 0x7f00, 1]);
+        assert!(Session::same_host(mapped, peer_ip));
+        assert!(!Session::same_host(IpAddr::from([127, 0, 0, 2]), peer_ip));
     }
 }
@@ -0,0 +1,93 @@
+// SITE子命令，新增子命令时在此注册名称和帮助信息，再在Session::site中分发
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiteCommand {
+    Help,
+    Chmod,
+    Umask,
+    Idle,
+    Utime,
+    Cpfr,
+    Cpto,
+    Undelete,
+    Quota,
+}
+
+impl SiteCommand {
+    pub const ALL: [Self; 9] = [
+        Self::Help,
+        Self::Chmod,
+        Self::Umask,
+        Self::Idle,
+        Self::Utime,
+        Self::Cpfr,
+        Self::Cpto,
+        Self::Undelete,
+        Self::Quota,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Help => "HELP",
+            Self::Chmod => "CHMOD",
+            Self::Umask => "UMASK",
+            Self::Idle => "IDLE",
+            Self::Utime => "UTIME",
+            Self::Cpfr => "CPFR",
+            Self::Cpto => "CPTO",
+            Self::Undelete => "UNDELETE",
+            Self::Quota => "QUOTA",
+        }
+    }
+
+    pub fn usage(self) -> &'static str {
+        match self {
+            Self::Help => "HELP",
+            Self::Chmod => "CHMOD <mode> <path>",
+            Self::Umask => "UMASK [mask]",
+            Self::Idle => "IDLE [seconds]",
+            Self::Utime => "UTIME <YYYYMMDDhhmm[ss]> <path>",
+            Self::Cpfr => "CPFR <path>",
+            Self::Cpto => "CPTO <path>",
+            Self::Undelete => "UNDELETE [id|path]",
+            Self::Quota => "QUOTA",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|command| command.name().eq_ignore_ascii_case(name))
+    }
+
+    // 未在site_commands中列出时是否允许，修改文件的命令需要单独开放
+    pub fn allowed_by_default(self) -> bool {
+        !matches!(self, Self::Chmod | Self::Utime | Self::Cpfr | Self::Cpto)
+    }
+}
+
+// 八进制的权限位，如644或0755，不允许setuid、setgid和sticky位
+pub fn parse_mode(s: &str) -> Option<u32> {
+    if s.is_empty() || s.len() > 4 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    u32::from_str_radix(s, 8).ok().filter(|&mode| mode <= 0o777)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SiteCommand::parse("chmod"), Some(SiteCommand::Chmod));
+        assert_eq!(SiteCommand::parse("EXEC"), None);
+        assert_eq!(parse_mode("644"), Some(0o644));
+        assert_eq!(parse_mode("0755"), Some(0o755));
+        assert_eq!(parse_mode("022"), Some(0o022));
+        assert_eq!(parse_mode("888"), None);
+        assert_eq!(parse_mode("4755"), None);
+        assert_eq!(parse_mode("02775"), None);
+        assert_eq!(parse_mode("+644"), None);
+        assert_eq!(parse_mode(""), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{io, path::Path, sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::config::{Config, StorageKind};
+
+mod archive;
+mod local;
+mod memory;
+#[cfg(feature = "s3")]
+mod s3;
+
+pub(crate) use archive::{ArchiveCache, ArchiveFs};
+pub use local::LocalFs;
+pub(crate) use local::{move_path, sweep_uploads};
+pub use memory::MemoryFs;
+#[cfg(feature = "s3")]
+pub use s3::S3Fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub kind: FileKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    // Unix权限位，后端不支持时为None
+    pub mode: Option<u32>,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+pub type ReadStream = Box<dyn AsyncRead + Send + Unpin>;
+pub type WriteStream = Box<dyn AsyncWrite + Send + Unpin>;
+
+// 文件存储后端，路径均为以"/"开头的规范化虚拟路径，由后端负责限制在根目录内
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    async fn list(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    // 从offset处开始读取文件
+    async fn open_read(&self, path: &Path, offset: u64) -> io::Result<ReadStream>;
+
+    // 创建或追加写入文件，写入完成后需要调用shutdown才算提交
+    async fn open_write(&self, path: &Path, append: bool) -> io::Result<WriteStream>;
+
+    // 保留的中止上传已接收的字节数，没有时返回None
+    async fn partial_len(&self, _path: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    // 继续写入保留的中止上传，shutdown后替换目标文件
+    async fn resume_write(&self, _path: &Path) -> io::Result<WriteStream> {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No partial upload to resume",
+        ))
+    }
+
+    async fn mkdir(&self, path: &Path) -> io::Result<()>;
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    // 递归删除目录
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    // overwrite为false时目标已存在则返回AlreadyExists
+    async fn rename(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()>;
+
+    // 复制文件，不支持复制目录，overwrite为false时目标已存在则返回AlreadyExists
+    async fn copy(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()>;
+
+    async fn set_mtime(&self, path: &Path, mtime: SystemTime) -> io::Result<()>;
+
+    // 设置Unix权限位
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()>;
+}
+
+// 按配置创建所有会话共享的存储，本地存储返回None，由每个会话使用当时配置的根目录
+pub(crate) fn from_config(config: &Config) -> io::Result<Option<Arc<dyn StorageBackend>>> {
+    Ok(match config.storage {
+        StorageKind::Local => None,
+        StorageKind::Memory => Some(Arc::new(MemoryFs::new())),
+        #[cfg(feature = "s3")]
+        StorageKind::S3 => match &config.s3 {
+            Some(s3) => Some(Arc::new(S3Fs::new(s3)?)),
+            None => None,
+        },
+        #[cfg(not(feature = "s3"))]
+        StorageKind::S3 => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "built without S3 support",
+            ));
+        }
+    })
+}
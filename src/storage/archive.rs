@@ -0,0 +1,566 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor, Read},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{DirEntry, FileKind, Metadata, ReadStream, StorageBackend, WriteStream};
+use crate::{listing, path};
+
+// 超过该大小的压缩包不展开浏览，解压后的tar也受此限制
+const MAX_ARCHIVE_SIZE: u64 = 64 * 1024 * 1024;
+// zip中单个文件解压后的大小上限，不信任压缩包中记录的大小
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+// 缓存的压缩包数据总大小
+const CACHE_BYTES: usize = 128 * 1024 * 1024;
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Archive contents are read-only",
+    )
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+}
+
+fn dir_metadata(modified: Option<SystemTime>) -> Metadata {
+    Metadata {
+        kind: FileKind::Dir,
+        len: 0,
+        modified,
+        mode: None,
+    }
+}
+
+// 压缩包内的路径统一为以"/"开头的形式，忽略..等不安全的部分
+fn normalize(name: &Path) -> Option<PathBuf> {
+    let mut path = PathBuf::from("/");
+    for component in name.components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (path.parent().is_some()).then_some(path)
+}
+
+struct Entry {
+    metadata: Metadata,
+    // zip中的序号或tar中数据的位置
+    location: Location,
+}
+
+enum Location {
+    Dir,
+    Zip(usize),
+    Tar(Range<usize>),
+}
+
+struct Archive {
+    entries: BTreeMap<PathBuf, Entry>,
+    // zip为原始数据，tar.gz为解压后的数据
+    data: Vec<u8>,
+}
+
+impl Archive {
+    fn parse(path: &Path, data: Vec<u8>) -> io::Result<Self> {
+        let name = path.to_string_lossy().to_lowercase();
+        let mut archive = if name.ends_with(".zip") {
+            Self::parse_zip(data)?
+        } else if name.ends_with(".tar") {
+            Self::parse_tar(data)?
+        } else {
+            let mut tar = Vec::new();
+            flate2::read::GzDecoder::new(&data[..])
+                .take(MAX_ARCHIVE_SIZE + 1)
+                .read_to_end(&mut tar)?;
+            if tar.len() as u64 > MAX_ARCHIVE_SIZE {
+                return Err(io::Error::other("Archive too large to browse"));
+            }
+            Self::parse_tar(tar)?
+        };
+        // 补充压缩包中没有单独记录的上级目录
+        let files: Vec<PathBuf> = archive.entries.keys().cloned().collect();
+        for file in files {
+            for parent in file.ancestors().skip(1) {
+                if parent.parent().is_none() {
+                    break;
+                }
+                archive
+                    .entries
+                    .entry(parent.to_path_buf())
+                    .or_insert(Entry {
+                        metadata: dir_metadata(None),
+                        location: Location::Dir,
+                    });
+            }
+        }
+        Ok(archive)
+    }
+
+    fn parse_zip(data: Vec<u8>) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut zip = zip::ZipArchive::new(Cursor::new(&data[..])).map_err(io::Error::other)?;
+        for i in 0..zip.len() {
+            let file = zip.by_index_raw(i).map_err(io::Error::other)?;
+            let Some(path) = file.enclosed_name().as_deref().and_then(normalize) else {
+                continue;
+            };
+            let modified = file.last_modified().and_then(|t| {
+                listing::system_time(
+                    t.year() as i64,
+                    t.month() as u32,
+                    t.day() as u32,
+                    t.hour() as u32,
+                    t.minute() as u32,
+                    t.second() as u32,
+                )
+            });
+            let entry = if file.is_dir() {
+                Entry {
+                    metadata: dir_metadata(modified),
+                    location: Location::Dir,
+                }
+            } else {
+                Entry {
+                    metadata: Metadata {
+                        kind: FileKind::File,
+                        len: file.size(),
+                        modified,
+                        mode: file.unix_mode().map(|mode| mode & 0o777),
+                    },
+                    location: Location::Zip(i),
+                }
+            };
+            entries.insert(path, entry);
+        }
+        Ok(Self { entries, data })
+    }
+
+    fn parse_tar(data: Vec<u8>) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut tar = tar::Archive::new(&data[..]);
+        for entry in tar.entries()? {
+            let entry = entry?;
+            let Some(path) = normalize(&entry.path()?) else {
+                continue;
+            };
+            let header = entry.header();
+            let modified = header
+                .mtime()
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+            let kind = header.entry_type();
+            let entry = if kind.is_dir() {
+                Entry {
+                    metadata: dir_metadata(modified),
+                    location: Location::Dir,
+                }
+            } else if kind.is_file() {
+                let start = entry.raw_file_position() as usize;
+                let len = entry.size();
+                Entry {
+                    metadata: Metadata {
+                        kind: FileKind::File,
+                        len,
+                        modified,
+                        mode: header.mode().ok().map(|mode| mode & 0o777),
+                    },
+                    location: Location::Tar(start..start + len as usize),
+                }
+            } else {
+                // 忽略符号链接等其他类型
+                continue;
+            };
+            entries.insert(path, entry);
+        }
+        Ok(Self { entries, data })
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        if path.parent().is_none() {
+            return Ok(dir_metadata(None));
+        }
+        self.entries
+            .get(path)
+            .map(|entry| entry.metadata.clone())
+            .ok_or_else(not_found)
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        if !self.metadata(path)?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(entry, _)| entry.parent() == Some(path))
+            .map(|(entry, info)| DirEntry {
+                name: entry
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                metadata: info.metadata.clone(),
+            })
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let entry = self.entries.get(path).ok_or_else(not_found)?;
+        match &entry.location {
+            Location::Dir => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "Is a directory",
+            )),
+            Location::Tar(range) => Ok(self.data[range.clone()].to_vec()),
+            Location::Zip(index) => {
+                let mut zip =
+                    zip::ZipArchive::new(Cursor::new(&self.data[..])).map_err(io::Error::other)?;
+                let file = zip.by_index(*index).map_err(io::Error::other)?;
+                let mut buf = Vec::new();
+                file.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut buf)?;
+                if buf.len() as u64 > MAX_ENTRY_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::FileTooLarge,
+                        "File too large to extract",
+                    ));
+                }
+                Ok(buf)
+            }
+        }
+    }
+}
+
+// 按(路径, 大小, 修改时间)缓存解析后的压缩包，所有会话共享
+#[derive(Clone, Default)]
+pub(crate) struct ArchiveCache {
+    archives: Arc<Mutex<Vec<CacheEntry>>>,
+}
+
+type CacheKey = (PathBuf, u64, Option<SystemTime>);
+type CacheEntry = (CacheKey, Arc<Archive>);
+
+impl ArchiveCache {
+    fn get(&self, key: &CacheKey) -> Option<Arc<Archive>> {
+        let mut archives = self.archives.lock().unwrap();
+        let i = archives.iter().position(|(k, _)| k == key)?;
+        // 移到末尾，最早使用的最先淘汰
+        let item = archives.remove(i);
+        let archive = item.1.clone();
+        archives.push(item);
+        Some(archive)
+    }
+
+    // 按数据总大小淘汰，而不是按个数
+    fn insert(&self, key: CacheKey, archive: Arc<Archive>) {
+        let mut archives = self.archives.lock().unwrap();
+        archives.retain(|(k, _)| k.0 != key.0);
+        let mut total: usize = archives.iter().map(|(_, a)| a.data.len()).sum();
+        while !archives.is_empty() && total + archive.data.len() > CACHE_BYTES {
+            total -= archives.remove(0).1.data.len();
+        }
+        archives.push((key, archive));
+    }
+}
+
+// 将压缩包作为只读目录浏览，其余操作交给内层存储
+pub(crate) struct ArchiveFs {
+    inner: Arc<dyn StorageBackend>,
+    cache: ArchiveCache,
+}
+
+impl ArchiveFs {
+    pub fn new(inner: Arc<dyn StorageBackend>, cache: ArchiveCache) -> Self {
+        Self { inner, cache }
+    }
+
+    // 路径经过压缩包文件时返回(压缩包路径, 包内路径)，压缩包本身的包内路径为"/"
+    async fn split(&self, path: &Path) -> Option<(PathBuf, PathBuf)> {
+        let (archive, inner) = path::split_archive(path)?;
+        match self.inner.metadata(&archive).await {
+            Ok(metadata) if metadata.is_file() => Some((archive, inner)),
+            _ => None,
+        }
+    }
+
+    // 路径是否位于压缩包内部（不包括压缩包本身）
+    async fn inside(&self, path: &Path) -> bool {
+        self.split(path)
+            .await
+            .is_some_and(|(_, inner)| inner.parent().is_some())
+    }
+
+    async fn load(&self, path: &Path) -> io::Result<Arc<Archive>> {
+        let metadata = self.inner.metadata(path).await?;
+        let key = (path.to_path_buf(), metadata.len, metadata.modified);
+        if let Some(archive) = self.cache.get(&key) {
+            return Ok(archive);
+        }
+        if metadata.len > MAX_ARCHIVE_SIZE {
+            return Err(io::Error::other("Archive too large to browse"));
+        }
+        let mut data = Vec::with_capacity(metadata.len as usize);
+        self.inner
+            .open_read(path, 0)
+            .await?
+            .read_to_end(&mut data)
+            .await?;
+        let archive_path = path.to_path_buf();
+        let archive = tokio::task::spawn_blocking(move || Archive::parse(&archive_path, data))
+            .await?
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Cannot read archive: {}", e),
+                )
+            })?;
+        let archive = Arc::new(archive);
+        self.cache.insert(key, archive.clone());
+        Ok(archive)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ArchiveFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.split(path).await {
+            Some((archive, inner)) if inner.parent().is_some() => {
+                self.load(&archive).await?.metadata(&inner)
+            }
+            _ => self.inner.metadata(path).await,
+        }
+    }
+
+    async fn list(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        match self.split(path).await {
+            Some((archive, inner)) => self.load(&archive).await?.list(&inner),
+            None => self.inner.list(path).await,
+        }
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> io::Result<ReadStream> {
+        match self.split(path).await {
+            Some((archive, inner)) if inner.parent().is_some() => {
+                let archive = self.load(&archive).await?;
+                // 解压可能耗时较长，不占用运行时线程
+                let data = tokio::task::spawn_blocking(move || archive.read(&inner)).await??;
+                let mut cursor = Cursor::new(data);
+                cursor.set_position(offset);
+                Ok(Box::new(cursor))
+            }
+            _ => self.inner.open_read(path, offset).await,
+        }
+    }
+
+    async fn open_write(&self, path: &Path, append: bool) -> io::Result<WriteStream> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.open_write(path, append).await
+    }
+
+    async fn partial_len(&self, path: &Path) -> io::Result<Option<u64>> {
+        if self.inside(path).await {
+            return Ok(None);
+        }
+        self.inner.partial_len(path).await
+    }
+
+    async fn resume_write(&self, path: &Path) -> io::Result<WriteStream> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.resume_write(path).await
+    }
+
+    async fn mkdir(&self, path: &Path) -> io::Result<()> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.mkdir(path).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.remove_file(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.remove_dir_all(path).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+        if self.inside(from).await || self.inside(to).await {
+            return Err(read_only());
+        }
+        self.inner.rename(from, to, overwrite).await
+    }
+
+    // 可以从压缩包中复制出文件，但不能复制到压缩包中
+    async fn copy(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+        if self.inside(to).await {
+            return Err(read_only());
+        }
+        if !self.inside(from).await {
+            return self.inner.copy(from, to, overwrite).await;
+        }
+        if self.metadata(from).await?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        // 从压缩包中复制出的文件通过上传写入，只能事先检查目标
+        if !overwrite && self.inner.metadata(to).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Target already exists",
+            ));
+        }
+        let mut reader = self.open_read(from, 0).await?;
+        let mut writer = self.inner.open_write(to, false).await?;
+        tokio::io::copy(&mut reader, &mut writer).await?;
+        writer.shutdown().await
+    }
+
+    async fn set_mtime(&self, path: &Path, mtime: SystemTime) -> io::Result<()> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.set_mtime(path, mtime).await
+    }
+
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        if self.inside(path).await {
+            return Err(read_only());
+        }
+        self.inner.set_permissions(path, mode).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::storage::MemoryFs;
+    use tokio::io::AsyncWriteExt;
+
+    async fn put(fs: &MemoryFs, path: &str, data: &[u8]) {
+        let mut w = fs.open_write(Path::new(path), false).await.unwrap();
+        w.write_all(data).await.unwrap();
+        w.shutdown().await.unwrap();
+    }
+
+    async fn read(fs: &ArchiveFs, path: &str) -> String {
+        let mut buf = String::new();
+        let mut r = fs.open_read(Path::new(path), 0).await.unwrap();
+        r.read_to_string(&mut buf).await.unwrap();
+        buf
+    }
+
+    fn names(entries: Vec<DirEntry>) -> Vec<String> {
+        entries.into_iter().map(|e| e.name).collect()
+    }
+
+    #[tokio::test]
+    async fn test_archive_fs() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("docs/readme.txt", options).unwrap();
+        zip.write_all(b"read me").unwrap();
+        zip.start_file("bin/tool", options).unwrap();
+        zip.write_all(b"binary").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "a/hello.txt", &b"hello"[..])
+            .unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&tar.into_inner().unwrap()).unwrap();
+        let tgz = gz.finish().unwrap();
+
+        let memory = MemoryFs::new();
+        put(&memory, "/bundle.zip", &zip).await;
+        put(&memory, "/src.tar.gz", &tgz).await;
+        let fs = ArchiveFs::new(Arc::new(memory), ArchiveCache::default());
+
+        // 压缩包本身仍然是文件
+        assert!(
+            fs.metadata(Path::new("/bundle.zip"))
+                .await
+                .unwrap()
+                .is_file()
+        );
+        assert_eq!(
+            names(fs.list(Path::new("/bundle.zip")).await.unwrap()),
+            ["bin", "docs"]
+        );
+        assert_eq!(
+            names(fs.list(Path::new("/bundle.zip/docs")).await.unwrap()),
+            ["readme.txt"]
+        );
+        assert_eq!(read(&fs, "/bundle.zip/docs/readme.txt").await, "read me");
+        assert_eq!(read(&fs, "/src.tar.gz/a/hello.txt").await, "hello");
+        let meta = fs.metadata(Path::new("/src.tar.gz/a")).await.unwrap();
+        assert!(meta.is_dir());
+        assert!(fs.metadata(Path::new("/bundle.zip/missing")).await.is_err());
+
+        let denied = fs.mkdir(Path::new("/bundle.zip/new")).await.unwrap_err();
+        assert_eq!(denied.kind(), io::ErrorKind::PermissionDenied);
+        assert!(
+            fs.remove_file(Path::new("/bundle.zip/docs/readme.txt"))
+                .await
+                .is_err()
+        );
+        assert!(
+            fs.open_write(Path::new("/src.tar.gz/a/x"), false)
+                .await
+                .is_err()
+        );
+        // 压缩包外的操作不受影响
+        fs.remove_file(Path::new("/bundle.zip")).await.unwrap();
+    }
+
+    #[test]
+    fn test_cache_bytes() {
+        let cache = ArchiveCache::default();
+        let key = |name: &str| (PathBuf::from(name), 0, None);
+        let archive = || {
+            Arc::new(Archive {
+                entries: BTreeMap::new(),
+                data: vec![0; CACHE_BYTES / 2],
+            })
+        };
+        cache.insert(key("/a.zip"), archive());
+        cache.insert(key("/b.zip"), archive());
+        assert!(cache.get(&key("/a.zip")).is_some());
+        // 超出总大小时淘汰最早使用的b.zip
+        cache.insert(key("/c.zip"), archive());
+        assert!(cache.get(&key("/b.zip")).is_none());
+        assert!(cache.get(&key("/a.zip")).is_some());
+        assert!(cache.get(&key("/c.zip")).is_some());
+    }
+}
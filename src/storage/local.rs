@@ -0,0 +1,772 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, ready},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use filetime::FileTime;
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWrite},
+};
+
+use super::{DirEntry, FileKind, Metadata, ReadStream, StorageBackend, WriteStream};
+
+// 本地磁盘存储，虚拟路径映射到root下
+pub struct LocalFs {
+    root: PathBuf,
+    keep_partial_uploads: bool,
+}
+
+impl LocalFs {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            root: dunce::canonicalize(&root).unwrap_or(root),
+            keep_partial_uploads: false,
+        }
+    }
+
+    // 上传中止时将已接收的部分保留为隐藏文件，供客户端续传，默认删除
+    pub fn keep_partial_uploads(mut self, keep: bool) -> Self {
+        self.keep_partial_uploads = keep;
+        self
+    }
+
+    // 虚拟路径转换为实际路径，解析符号链接后仍需位于根目录内
+    pub(crate) fn real_path(&self, path: &Path) -> io::Result<PathBuf> {
+        let relative = path.strip_prefix("/").unwrap_or(path);
+        let real = self.root.join(relative);
+        // 目标可能还不存在，检查最近的已存在的上级目录
+        let existing = real
+            .ancestors()
+            .find_map(|p| dunce::canonicalize(p).ok())
+            .unwrap_or_else(|| self.root.clone());
+        if !existing.starts_with(&self.root) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Path is outside of the root directory",
+            ));
+        }
+        Ok(real)
+    }
+
+    fn writer(&self, file: File, temp: PathBuf, target: PathBuf) -> io::Result<AtomicWriter> {
+        let partial = match self.keep_partial_uploads {
+            true => Some(partial_path(&target)?),
+            false => None,
+        };
+        Ok(AtomicWriter {
+            file: Some(file),
+            temp,
+            target,
+            partial,
+            commit: None,
+            committed: false,
+        })
+    }
+
+    fn convert(meta: &std::fs::Metadata) -> Metadata {
+        let kind = if meta.is_dir() {
+            FileKind::Dir
+        } else if meta.file_type().is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::File
+        };
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(meta.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+        Metadata {
+            kind,
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            mode,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let meta = tokio::fs::metadata(self.real_path(path)?).await?;
+        Ok(Self::convert(&meta))
+    }
+
+    async fn list(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut dir = tokio::fs::read_dir(self.real_path(path)?).await?;
+        let mut entries = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // 上传用的临时文件不对外显示
+            if is_upload_name(&name) {
+                continue;
+            }
+            let Ok(meta) = entry.metadata().await else {
+                continue;
+            };
+            entries.push(DirEntry {
+                name,
+                metadata: Self::convert(&meta),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> io::Result<ReadStream> {
+        let mut file = tokio::fs::File::open(self.real_path(path)?).await?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset)).await?;
+        }
+        Ok(Box::new(file))
+    }
+
+    async fn open_write(&self, path: &Path, append: bool) -> io::Result<WriteStream> {
+        let real = self.real_path(path)?;
+        if append {
+            let file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(real)
+                .await?;
+            return Ok(Box::new(file));
+        }
+        let existing = match tokio::fs::metadata(&real).await {
+            Ok(meta) if meta.is_dir() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::IsADirectory,
+                    "Is a directory",
+                ));
+            }
+            Ok(meta) => Some(meta),
+            Err(_) => None,
+        };
+        let temp = temp_path(&real)?;
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .await?;
+        // 替换已有文件时保留其权限
+        if let Some(meta) = existing {
+            let _ = file.set_permissions(meta.permissions()).await;
+        }
+        Ok(Box::new(self.writer(file, temp, real)?))
+    }
+
+    async fn partial_len(&self, path: &Path) -> io::Result<Option<u64>> {
+        if !self.keep_partial_uploads {
+            return Ok(None);
+        }
+        match tokio::fs::metadata(partial_path(&self.real_path(path)?)?).await {
+            Ok(meta) if meta.is_file() => Ok(Some(meta.len())),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn resume_write(&self, path: &Path) -> io::Result<WriteStream> {
+        let real = self.real_path(path)?;
+        let partial = partial_path(&real)?;
+        let file = File::options().append(true).open(&partial).await?;
+        Ok(Box::new(self.writer(file, partial, real)?))
+    }
+
+    async fn mkdir(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::create_dir(self.real_path(path)?).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(self.real_path(path)?).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let real = self.real_path(path)?;
+        if real == self.root {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Cannot remove the root directory",
+            ));
+        }
+        tokio::fs::remove_dir_all(real).await
+    }
+
+    // 源和目标位于不同的文件系统时改为复制后删除
+    async fn rename(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+        let (from, to) = (self.real_path(from)?, self.real_path(to)?);
+        if from == to {
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || {
+            let res = match overwrite {
+                true => std::fs::rename(&from, &to),
+                false => rename_noreplace(&from, &to),
+            };
+            match res {
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                    move_across_devices(&from, &to, overwrite)
+                }
+                res => res,
+            }
+        })
+        .await?
+    }
+
+    // 先复制到目标目录下的临时文件再重命名，源和目标可以位于不同的文件系统
+    async fn copy(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+        let (from, to) = (self.real_path(from)?, self.real_path(to)?);
+        tokio::task::spawn_blocking(move || {
+            if std::fs::metadata(&from)?.is_dir() || to.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::IsADirectory,
+                    "Is a directory",
+                ));
+            }
+            let temp = temp_path(&to)?;
+            let res = std::fs::copy(&from, &temp).and_then(|_| match overwrite {
+                true => std::fs::rename(&temp, &to),
+                false => rename_noreplace(&temp, &to),
+            });
+            if res.is_err() {
+                let _ = std::fs::remove_file(&temp);
+            }
+            res
+        })
+        .await?
+    }
+
+    async fn set_mtime(&self, path: &Path, mtime: SystemTime) -> io::Result<()> {
+        let real = self.real_path(path)?;
+        tokio::task::spawn_blocking(move || {
+            std::fs::File::options()
+                .write(true)
+                .open(real)?
+                .set_modified(mtime)
+        })
+        .await?
+    }
+
+    #[cfg(unix)]
+    async fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let real = self.real_path(path)?;
+        tokio::fs::set_permissions(real, std::fs::Permissions::from_mode(mode)).await
+    }
+
+    #[cfg(not(unix))]
+    async fn set_permissions(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Permissions are not supported on this platform",
+        ))
+    }
+}
+
+// 同一目录下的隐藏临时文件，保证可以原子地重命名
+fn temp_path(target: &Path) -> io::Result<PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    upload_path(
+        target,
+        &format!(
+            "{}-{}",
+            instance_id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    )
+}
+
+// 每次启动随机生成，多个实例共用根目录时临时文件名不会冲突，容器中的进程号总是相同
+fn instance_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| format!("{:016x}", RandomState::new().build_hasher().finish()))
+}
+
+// 保留的中止上传，每个目标文件最多一个
+fn partial_path(target: &Path) -> io::Result<PathBuf> {
+    upload_path(target, PARTIAL)
+}
+
+const PARTIAL: &str = "partial";
+
+fn upload_path(target: &Path, tag: &str) -> io::Result<PathBuf> {
+    let name = target
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
+    Ok(target.with_file_name(format!(".{}.{}.upload", name.to_string_lossy(), tag)))
+}
+
+// 解析临时文件名，保留的中止上传返回Some(true)
+fn parse_upload_name(name: &str) -> Option<bool> {
+    let (_, tag) = name
+        .strip_prefix('.')?
+        .strip_suffix(".upload")?
+        .rsplit_once('.')?;
+    if tag == PARTIAL {
+        return Some(true);
+    }
+    let (id, n) = tag.split_once('-')?;
+    let valid =
+        id.len() == 16 && id.bytes().all(|b| b.is_ascii_hexdigit()) && n.parse::<u64>().is_ok();
+    valid.then_some(false)
+}
+
+fn is_upload_name(name: &str) -> bool {
+    parse_upload_name(name).is_some()
+}
+
+// 删除超过max_age没有写入的临时文件，即崩溃的进程留下的文件，不保留中止的上传时一并删除
+// 其他实例正在写入的文件修改时间较新，不会被删除；在阻塞线程中调用，返回删除的文件数
+pub(crate) fn sweep_uploads(
+    dir: &Path,
+    keep_partial: bool,
+    max_age: Duration,
+) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            match sweep_uploads(&entry.path(), keep_partial, max_age) {
+                Ok(n) => removed += n,
+                Err(e) => log::warn!("Failed to sweep {}: {}", entry.path().display(), e),
+            }
+            continue;
+        }
+        let stale = match parse_upload_name(&entry.file_name().to_string_lossy()) {
+            Some(partial) if file_type.is_file() && !(partial && keep_partial) => entry
+                .metadata()?
+                .modified()?
+                .elapsed()
+                .is_ok_and(|age| age >= max_age),
+            _ => false,
+        };
+        if stale {
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(e) => log::warn!("Failed to remove {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+// 移动文件或目录，不覆盖已存在的目标，跨文件系统时复制后删除，在阻塞线程中调用
+pub(crate) fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match rename_noreplace(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => move_across_devices(from, to, false),
+        res => res,
+    }
+}
+
+// 目标已存在时返回AlreadyExists，检查和重命名是同一个操作，不会覆盖并发创建的目标
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+        let c_path = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))
+        };
+        let (c_from, c_to) = (c_path(from)?, c_path(to)?);
+        // SAFETY: 两个路径都是以NUL结尾的有效字符串
+        let res = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                c_from.as_ptr(),
+                libc::AT_FDCWD,
+                c_to.as_ptr(),
+                libc::RENAME_NOREPLACE,
+            )
+        };
+        if res == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        // 文件系统不支持RENAME_NOREPLACE时使用下面的方法
+        if !matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) {
+            return Err(e);
+        }
+    }
+    // 文件通过硬链接移动，目标已存在时链接失败；目录只能先检查
+    if std::fs::symlink_metadata(from)?.is_dir() {
+        if to.symlink_metadata().is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Target already exists",
+            ));
+        }
+        return std::fs::rename(from, to);
+    }
+    std::fs::hard_link(from, to)?;
+    std::fs::remove_file(from)
+}
+
+// 复制完成后才删除源，复制失败时删除已复制的部分
+fn move_across_devices(from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+    let meta = std::fs::symlink_metadata(from)?;
+    if meta.is_dir() {
+        std::fs::create_dir(to)?;
+        if let Err(e) = copy_dir(from, to) {
+            let _ = std::fs::remove_dir_all(to);
+            return Err(e);
+        }
+        return std::fs::remove_dir_all(from);
+    }
+    if to.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            "Is a directory",
+        ));
+    }
+    let temp = temp_path(to)?;
+    let res = copy_file(from, &temp, &meta).and_then(|_| match overwrite {
+        true => std::fs::rename(&temp, to),
+        false => rename_noreplace(&temp, to),
+    });
+    if res.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    res?;
+    std::fs::remove_file(from)
+}
+
+// 复制文件内容、权限和修改时间
+fn copy_file(from: &Path, to: &Path, meta: &std::fs::Metadata) -> io::Result<()> {
+    std::fs::copy(from, to)?;
+    copy_times(to, meta)
+}
+
+fn copy_times(to: &Path, meta: &std::fs::Metadata) -> io::Result<()> {
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(meta),
+        FileTime::from_last_modification_time(meta),
+    )
+}
+
+// 递归复制目录内容到已创建的目标目录，符号链接按原样重建
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            std::fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            copy_file(&entry.path(), &target, &entry.metadata()?)?;
+        }
+    }
+    // 目录内容复制完成后再设置，否则会被复制过程更新
+    let meta = std::fs::metadata(from)?;
+    std::fs::set_permissions(to, meta.permissions())?;
+    copy_times(to, &meta)
+}
+
+type Commit = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+// 写入临时文件，shutdown时同步到磁盘并重命名为目标文件
+// 未完成shutdown就被drop时视为上传失败，保留中止的上传时临时文件改名为partial
+struct AtomicWriter {
+    file: Option<File>,
+    temp: PathBuf,
+    target: PathBuf,
+    partial: Option<PathBuf>,
+    commit: Option<Commit>,
+    committed: bool,
+}
+
+impl AtomicWriter {
+    fn file(&mut self) -> io::Result<Pin<&mut File>> {
+        match &mut self.file {
+            Some(file) => Ok(Pin::new(file)),
+            None => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Upload already finished",
+            )),
+        }
+    }
+}
+
+impl AsyncWrite for AtomicWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.file()?.poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.file.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        self.file()?.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.committed {
+            return Poll::Ready(Ok(()));
+        }
+        if self.commit.is_none() {
+            let file = self.file.take();
+            let (temp, target) = (self.temp.clone(), self.target.clone());
+            // 新的上传完成后之前保留的中止上传不再需要
+            let stale = self.partial.clone().filter(|partial| *partial != temp);
+            self.commit = Some(Box::pin(async move {
+                if let Some(file) = file {
+                    file.sync_all().await?;
+                }
+                tokio::fs::rename(temp, target).await?;
+                if let Some(stale) = stale {
+                    let _ = tokio::fs::remove_file(stale).await;
+                }
+                Ok(())
+            }));
+        }
+        let res = ready!(self.commit.as_mut().unwrap().as_mut().poll(cx));
+        self.commit = None;
+        self.committed = res.is_ok();
+        Poll::Ready(res)
+    }
+}
+
+impl Drop for AtomicWriter {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        drop(self.file.take());
+        let (temp, partial) = (self.temp.clone(), self.partial.take());
+        let cleanup = move || {
+            let res = match partial {
+                Some(partial) if partial == temp => Ok(()),
+                Some(partial) => std::fs::rename(&temp, partial),
+                None => std::fs::remove_file(&temp),
+            };
+            if let Err(e) = res
+                && e.kind() != io::ErrorKind::NotFound
+            {
+                log::warn!(
+                    "Failed to clean up partial upload {}: {}",
+                    temp.display(),
+                    e
+                );
+            }
+        };
+        // 不在运行时线程上执行阻塞的文件操作
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(cleanup)),
+            Err(_) => cleanup(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_local_fs() {
+        let root = std::env::temp_dir().join(format!("ftpserver-localfs-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let fs = LocalFs::new(&root);
+
+        fs.mkdir(Path::new("/dir")).await.unwrap();
+        let mut w = fs.open_write(Path::new("/dir/a.txt"), false).await.unwrap();
+        w.write_all(b"hello").await.unwrap();
+        w.shutdown().await.unwrap();
+        let mut w = fs.open_write(Path::new("/dir/a.txt"), true).await.unwrap();
+        w.write_all(b" world").await.unwrap();
+        w.shutdown().await.unwrap();
+
+        let mut buf = String::new();
+        let mut r = fs.open_read(Path::new("/dir/a.txt"), 6).await.unwrap();
+        r.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "world");
+
+        let entries = fs.list(Path::new("/dir")).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "a.txt");
+        assert_eq!(entries[0].metadata.len, 11);
+
+        fs.rename(Path::new("/dir/a.txt"), Path::new("/b.txt"), false)
+            .await
+            .unwrap();
+        assert!(fs.metadata(Path::new("/b.txt")).await.unwrap().is_file());
+        fs.copy(Path::new("/b.txt"), Path::new("/dir/c.txt"), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(root.join("dir/c.txt")).unwrap(),
+            b"hello world"
+        );
+        let err = fs
+            .rename(Path::new("/dir/c.txt"), Path::new("/b.txt"), false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(
+            fs.copy(Path::new("/b.txt"), Path::new("/dir"), true)
+                .await
+                .is_err()
+        );
+        assert!(fs.remove_dir_all(Path::new("/")).await.is_err());
+        fs.remove_dir_all(Path::new("/dir")).await.unwrap();
+        fs.remove_file(Path::new("/b.txt")).await.unwrap();
+        assert!(fs.list(Path::new("/")).await.unwrap().is_empty());
+
+        // 上传完成前目标文件保持原样，中止的上传不留下文件
+        let mut w = fs.open_write(Path::new("/c.txt"), false).await.unwrap();
+        w.write_all(b"partial").await.unwrap();
+        assert!(fs.metadata(Path::new("/c.txt")).await.is_err());
+        // 临时文件不出现在列表中
+        assert!(fs.list(Path::new("/")).await.unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 1);
+        drop(w);
+        settle(|| std::fs::read_dir(&root).unwrap().count() == 0).await;
+        assert!(fs.open_write(Path::new("/"), false).await.is_err());
+        assert_eq!(fs.partial_len(Path::new("/c.txt")).await.unwrap(), None);
+
+        // 保留的中止上传不替换目标文件，续传完成后才替换
+        let fs = fs.keep_partial_uploads(true);
+        std::fs::write(root.join("c.txt"), b"old").unwrap();
+        let mut w = fs.open_write(Path::new("/c.txt"), false).await.unwrap();
+        w.write_all(b"partial").await.unwrap();
+        drop(w);
+        settle(|| root.join(".c.txt.partial.upload").exists()).await;
+        assert_eq!(std::fs::read(root.join("c.txt")).unwrap(), b"old");
+        assert_eq!(fs.list(Path::new("/")).await.unwrap().len(), 1);
+        assert_eq!(fs.partial_len(Path::new("/c.txt")).await.unwrap(), Some(7));
+        let mut w = fs.resume_write(Path::new("/c.txt")).await.unwrap();
+        w.write_all(b" upload").await.unwrap();
+        drop(w);
+        let partial = root.join(".c.txt.partial.upload");
+        settle(|| std::fs::metadata(&partial).is_ok_and(|m| m.len() == 14)).await;
+        assert_eq!(fs.partial_len(Path::new("/c.txt")).await.unwrap(), Some(14));
+        let mut w = fs.resume_write(Path::new("/c.txt")).await.unwrap();
+        w.write_all(b"!").await.unwrap();
+        w.shutdown().await.unwrap();
+        assert_eq!(
+            std::fs::read(root.join("c.txt")).unwrap(),
+            b"partial upload!"
+        );
+        assert_eq!(fs.partial_len(Path::new("/c.txt")).await.unwrap(), None);
+        fs.remove_file(Path::new("/c.txt")).await.unwrap();
+
+        #[cfg(unix)]
+        {
+            // 指向根目录外的符号链接
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("escape")).unwrap();
+            assert!(fs.list(Path::new("/escape")).await.is_err());
+            std::fs::remove_file(root.join("escape")).unwrap();
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    // 中止的上传在阻塞线程中清理，等待清理完成
+    async fn settle(done: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("partial upload was not cleaned up");
+    }
+
+    #[test]
+    fn test_sweep_uploads() {
+        let root = std::env::temp_dir().join(format!("ftpserver-sweep-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        for name in [
+            "a.txt",
+            ".a.txt.0123456789abcdef-0.upload",
+            "sub/.b.txt.fedcba9876543210-5.upload",
+            "sub/.b.txt.partial.upload",
+            ".notes.upload",
+        ] {
+            std::fs::write(root.join(name), b"x").unwrap();
+            filetime::set_file_mtime(root.join(name), old.into()).unwrap();
+        }
+        // 另一个实例正在写入的临时文件
+        let other = root.join(".c.txt.00000000deadbeef-3.upload");
+        let mut writing = std::fs::File::create(&other).unwrap();
+        let current = temp_path(&root.join("d.txt")).unwrap();
+        std::fs::write(&current, b"x").unwrap();
+        assert!(is_upload_name(
+            &current.file_name().unwrap().to_string_lossy()
+        ));
+        assert!(!is_upload_name(".notes.upload"));
+        assert!(!is_upload_name(".a.txt.1-0.upload"));
+
+        let max_age = Duration::from_secs(3600);
+        assert_eq!(sweep_uploads(&root, true, max_age).unwrap(), 2);
+        assert!(root.join("sub/.b.txt.partial.upload").exists());
+        assert!(current.exists());
+        std::io::Write::write_all(&mut writing, b"more").unwrap();
+        assert_eq!(sweep_uploads(&root, false, max_age).unwrap(), 1);
+        assert!(other.exists());
+        assert!(root.join("a.txt").exists());
+        assert!(root.join(".notes.upload").exists());
+        drop(writing);
+        assert_eq!(std::fs::read(&other).unwrap(), b"more");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_move_across_devices() {
+        let root = std::env::temp_dir().join(format!("ftpserver-move-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src/sub")).unwrap();
+        std::fs::write(root.join("src/a.txt"), b"a").unwrap();
+        std::fs::write(root.join("src/sub/b.txt"), b"b").unwrap();
+
+        let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        filetime::set_file_mtime(root.join("src/sub/b.txt"), mtime.into()).unwrap();
+        move_across_devices(&root.join("src"), &root.join("dst"), false).unwrap();
+        assert!(!root.join("src").exists());
+        assert_eq!(std::fs::read(root.join("dst/sub/b.txt")).unwrap(), b"b");
+        let meta = std::fs::metadata(root.join("dst/sub/b.txt")).unwrap();
+        assert_eq!(meta.modified().unwrap(), mtime);
+
+        move_across_devices(&root.join("dst/a.txt"), &root.join("a.txt"), false).unwrap();
+        assert_eq!(std::fs::read(root.join("a.txt")).unwrap(), b"a");
+        assert!(!root.join("dst/a.txt").exists());
+        // 不覆盖时目标文件和源都保持原样
+        std::fs::write(root.join("b.txt"), b"b").unwrap();
+        let err = move_across_devices(&root.join("a.txt"), &root.join("b.txt"), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(root.join("b.txt")).unwrap(), b"b");
+        assert!(root.join("a.txt").exists());
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 3);
+        move_across_devices(&root.join("a.txt"), &root.join("b.txt"), true).unwrap();
+        assert_eq!(std::fs::read(root.join("b.txt")).unwrap(), b"a");
+        // 目标目录已存在时不合并
+        std::fs::create_dir(root.join("other")).unwrap();
+        assert!(move_across_devices(&root.join("dst"), &root.join("other"), true).is_err());
+        assert!(root.join("dst/sub/b.txt").exists());
+        assert!(root.join("other").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
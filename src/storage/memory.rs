@@ -0,0 +1,467 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use tokio::io::AsyncWrite;
+
+use super::{DirEntry, FileKind, Metadata, ReadStream, StorageBackend, WriteStream};
+
+enum Node {
+    File {
+        data: Arc<Vec<u8>>,
+        modified: SystemTime,
+    },
+    Dir {
+        children: BTreeMap<String, Node>,
+        modified: SystemTime,
+    },
+}
+
+impl Node {
+    fn new_dir() -> Self {
+        Node::Dir {
+            children: BTreeMap::new(),
+            modified: SystemTime::now(),
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        match self {
+            Node::File { data, modified } => Metadata {
+                kind: FileKind::File,
+                len: data.len() as u64,
+                modified: Some(*modified),
+                mode: None,
+            },
+            Node::Dir { children, modified } => Metadata {
+                kind: FileKind::Dir,
+                len: children.len() as u64,
+                modified: Some(*modified),
+                mode: None,
+            },
+        }
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+}
+
+fn not_a_dir() -> io::Error {
+    io::Error::new(io::ErrorKind::NotADirectory, "Not a directory")
+}
+
+fn is_a_dir() -> io::Error {
+    io::Error::new(io::ErrorKind::IsADirectory, "Is a directory")
+}
+
+// 虚拟路径拆分为各级名称
+fn names(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn lookup<'a>(mut node: &'a Node, names: &[String]) -> io::Result<&'a Node> {
+    for name in names {
+        match node {
+            Node::Dir { children, .. } => node = children.get(name).ok_or_else(not_found)?,
+            Node::File { .. } => return Err(not_a_dir()),
+        }
+    }
+    Ok(node)
+}
+
+// 返回上级目录的子节点表、修改时间和最后一级名称
+fn parent_mut<'a>(
+    root: &'a mut Node,
+    names: &'a [String],
+) -> io::Result<(&'a mut BTreeMap<String, Node>, &'a mut SystemTime, &'a str)> {
+    let Some((name, parents)) = names.split_last() else {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Cannot modify the root directory",
+        ));
+    };
+    let mut node = root;
+    for parent in parents {
+        match node {
+            Node::Dir { children, .. } => node = children.get_mut(parent).ok_or_else(not_found)?,
+            Node::File { .. } => return Err(not_a_dir()),
+        }
+    }
+    match node {
+        Node::Dir { children, modified } => Ok((children, modified, name)),
+        Node::File { .. } => Err(not_a_dir()),
+    }
+}
+
+// 内存存储，服务器退出后内容丢失，用于测试和临时服务
+#[derive(Clone)]
+pub struct MemoryFs {
+    root: Arc<Mutex<Node>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(Mutex::new(Node::new_dir())),
+        }
+    }
+
+    // 写入文件内容，上级目录必须已存在
+    fn put(&self, path: &Path, data: Vec<u8>) -> io::Result<()> {
+        let names = names(path);
+        let mut root = self.root.lock().unwrap();
+        let (children, dir_modified, name) = parent_mut(&mut root, &names)?;
+        if let Some(Node::Dir { .. }) = children.get(name) {
+            return Err(is_a_dir());
+        }
+        let now = SystemTime::now();
+        children.insert(
+            name.to_string(),
+            Node::File {
+                data: Arc::new(data),
+                modified: now,
+            },
+        );
+        *dir_modified = now;
+        Ok(())
+    }
+}
+
+// 读取时共享文件内容，不复制数据
+struct Shared(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for Shared {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// 写入缓存在内存中，shutdown时替换文件内容
+struct MemoryWriter {
+    fs: MemoryFs,
+    path: PathBuf,
+    buf: Vec<u8>,
+}
+
+impl AsyncWrite for MemoryWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let data = std::mem::take(&mut self.buf);
+        Poll::Ready(self.fs.put(&self.path, data))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryFs {
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let root = self.root.lock().unwrap();
+        Ok(lookup(&root, &names(path))?.metadata())
+    }
+
+    async fn list(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let root = self.root.lock().unwrap();
+        match lookup(&root, &names(path))? {
+            Node::Dir { children, .. } => Ok(children
+                .iter()
+                .map(|(name, node)| DirEntry {
+                    name: name.clone(),
+                    metadata: node.metadata(),
+                })
+                .collect()),
+            Node::File { .. } => Err(not_a_dir()),
+        }
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> io::Result<ReadStream> {
+        let root = self.root.lock().unwrap();
+        match lookup(&root, &names(path))? {
+            Node::File { data, .. } => {
+                let mut cursor = io::Cursor::new(Shared(data.clone()));
+                cursor.set_position(offset);
+                Ok(Box::new(cursor))
+            }
+            Node::Dir { .. } => Err(is_a_dir()),
+        }
+    }
+
+    async fn open_write(&self, path: &Path, append: bool) -> io::Result<WriteStream> {
+        let names = names(path);
+        // 打开时只检查上级目录，文件内容在shutdown时才替换
+        let buf = {
+            let mut root = self.root.lock().unwrap();
+            let (children, _, name) = parent_mut(&mut root, &names)?;
+            match children.get(name) {
+                Some(Node::File { data, .. }) if append => data.to_vec(),
+                Some(Node::Dir { .. }) => return Err(is_a_dir()),
+                _ => Vec::new(),
+            }
+        };
+        Ok(Box::new(MemoryWriter {
+            fs: self.clone(),
+            path: path.to_path_buf(),
+            buf,
+        }))
+    }
+
+    async fn mkdir(&self, path: &Path) -> io::Result<()> {
+        let names = names(path);
+        let mut root = self.root.lock().unwrap();
+        let (children, modified, name) = parent_mut(&mut root, &names).map_err(|e| {
+            if names.is_empty() {
+                io::Error::new(io::ErrorKind::AlreadyExists, "File exists")
+            } else {
+                e
+            }
+        })?;
+        if children.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists"));
+        }
+        children.insert(name.to_string(), Node::new_dir());
+        *modified = SystemTime::now();
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let names = names(path);
+        let mut root = self.root.lock().unwrap();
+        let (children, modified, name) = parent_mut(&mut root, &names)?;
+        match children.get(name) {
+            Some(Node::File { .. }) => {}
+            Some(Node::Dir { .. }) => return Err(is_a_dir()),
+            None => return Err(not_found()),
+        }
+        children.remove(name);
+        *modified = SystemTime::now();
+        Ok(())
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let names = names(path);
+        let mut root = self.root.lock().unwrap();
+        let (children, modified, name) = parent_mut(&mut root, &names)?;
+        match children.get(name) {
+            Some(Node::Dir { .. }) => {}
+            Some(Node::File { .. }) => return Err(not_a_dir()),
+            None => return Err(not_found()),
+        }
+        children.remove(name);
+        *modified = SystemTime::now();
+        Ok(())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+        let (from, to) = (names(from), names(to));
+        if to.len() > from.len() && to.starts_with(&from) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot move a directory into itself",
+            ));
+        }
+        if from == to {
+            return Ok(());
+        }
+        let mut root = self.root.lock().unwrap();
+        // 先检查目标位置，避免移除源节点后才发现无法放入
+        {
+            let (children, _, name) = parent_mut(&mut root, &to)?;
+            match children.get(name) {
+                Some(Node::Dir { .. }) => return Err(is_a_dir()),
+                Some(_) if !overwrite => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "Target already exists",
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let now = SystemTime::now();
+        let (children, modified, name) = parent_mut(&mut root, &from)?;
+        let node = children.remove(name).ok_or_else(not_found)?;
+        *modified = now;
+        let (children, modified, name) = parent_mut(&mut root, &to)?;
+        if matches!(node, Node::Dir { .. }) && children.contains_key(name) {
+            // 目录不能覆盖文件，放回原处
+            let (children, _, from_name) = parent_mut(&mut root, &from)?;
+            children.insert(from_name.to_string(), node);
+            return Err(not_a_dir());
+        }
+        children.insert(name.to_string(), node);
+        *modified = now;
+        Ok(())
+    }
+
+    // 文件内容共享，写入时整体替换，不需要复制数据
+    async fn copy(&self, from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+        let (from, to) = (names(from), names(to));
+        let mut root = self.root.lock().unwrap();
+        let (children, _, name) = parent_mut(&mut root, &from)?;
+        let data = match children.get(name).ok_or_else(not_found)? {
+            Node::File { data, .. } => data.clone(),
+            Node::Dir { .. } => return Err(is_a_dir()),
+        };
+        let now = SystemTime::now();
+        let (children, modified, name) = parent_mut(&mut root, &to)?;
+        match children.get(name) {
+            Some(Node::Dir { .. }) => return Err(is_a_dir()),
+            Some(_) if !overwrite => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Target already exists",
+                ));
+            }
+            _ => {}
+        }
+        children.insert(
+            name.to_string(),
+            Node::File {
+                data,
+                modified: now,
+            },
+        );
+        *modified = now;
+        Ok(())
+    }
+
+    async fn set_mtime(&self, path: &Path, mtime: SystemTime) -> io::Result<()> {
+        let names = names(path);
+        let mut root = self.root.lock().unwrap();
+        let node = match parent_mut(&mut root, &names) {
+            Ok((children, _, name)) => children.get_mut(name).ok_or_else(not_found)?,
+            Err(_) if names.is_empty() => &mut *root,
+            Err(e) => return Err(e),
+        };
+        match node {
+            Node::File { modified, .. } | Node::Dir { modified, .. } => *modified = mtime,
+        }
+        Ok(())
+    }
+
+    async fn set_permissions(&self, path: &Path, _mode: u32) -> io::Result<()> {
+        self.metadata(path).await?;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Permissions are not supported by memory storage",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn write(fs: &MemoryFs, path: &str, data: &[u8], append: bool) -> io::Result<()> {
+        let mut w = fs.open_write(Path::new(path), append).await?;
+        w.write_all(data).await?;
+        w.shutdown().await
+    }
+
+    async fn read(fs: &MemoryFs, path: &str, offset: u64) -> String {
+        let mut buf = String::new();
+        let mut r = fs.open_read(Path::new(path), offset).await.unwrap();
+        r.read_to_string(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_memory_fs() {
+        let fs = MemoryFs::new();
+        fs.mkdir(Path::new("/dir")).await.unwrap();
+        assert!(fs.mkdir(Path::new("/dir")).await.is_err());
+        assert!(fs.mkdir(Path::new("/missing/dir")).await.is_err());
+
+        write(&fs, "/dir/a.txt", b"hello", false).await.unwrap();
+        write(&fs, "/dir/a.txt", b" world", true).await.unwrap();
+        assert_eq!(read(&fs, "/dir/a.txt", 6).await, "world");
+        assert!(write(&fs, "/missing/a.txt", b"x", false).await.is_err());
+        assert!(write(&fs, "/dir", b"x", false).await.is_err());
+
+        let entries = fs.list(Path::new("/dir")).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "a.txt");
+        assert_eq!(entries[0].metadata.len, 11);
+        assert!(fs.list(Path::new("/dir/a.txt")).await.is_err());
+
+        assert!(
+            fs.rename(Path::new("/dir"), Path::new("/dir/sub"), false)
+                .await
+                .is_err()
+        );
+        fs.rename(Path::new("/dir/a.txt"), Path::new("/b.txt"), false)
+            .await
+            .unwrap();
+        assert!(fs.metadata(Path::new("/b.txt")).await.unwrap().is_file());
+        assert!(fs.metadata(Path::new("/dir/a.txt")).await.is_err());
+
+        fs.copy(Path::new("/b.txt"), Path::new("/dir/c.txt"), false)
+            .await
+            .unwrap();
+        let copied = fs.metadata(Path::new("/dir/c.txt")).await.unwrap();
+        assert_eq!(
+            copied.len,
+            fs.metadata(Path::new("/b.txt")).await.unwrap().len
+        );
+        assert!(
+            fs.copy(Path::new("/dir"), Path::new("/d"), true)
+                .await
+                .is_err()
+        );
+        assert!(
+            fs.copy(Path::new("/b.txt"), Path::new("/dir/c.txt"), false)
+                .await
+                .is_err()
+        );
+        let err = fs
+            .rename(Path::new("/dir/c.txt"), Path::new("/b.txt"), false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        fs.rename(Path::new("/dir/c.txt"), Path::new("/b.txt"), true)
+            .await
+            .unwrap();
+        assert!(fs.metadata(Path::new("/dir/c.txt")).await.is_err());
+
+        let mtime = SystemTime::UNIX_EPOCH;
+        fs.set_mtime(Path::new("/b.txt"), mtime).await.unwrap();
+        let meta = fs.metadata(Path::new("/b.txt")).await.unwrap();
+        assert_eq!(meta.modified, Some(mtime));
+
+        assert!(fs.remove_dir_all(Path::new("/")).await.is_err());
+        assert!(fs.remove_file(Path::new("/dir")).await.is_err());
+        fs.remove_dir_all(Path::new("/dir")).await.unwrap();
+        fs.remove_file(Path::new("/b.txt")).await.unwrap();
+        assert!(fs.list(Path::new("/")).await.unwrap().is_empty());
+    }
+}
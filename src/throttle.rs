@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::config::Config;
+
+struct Bucket {
+    // 字节每秒，None表示不限制
+    rate: Option<u64>,
+    tokens: f64,
+    updated: Instant,
+}
+
+// 令牌桶限速，令牌上限为一秒的流量
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Option<u64>) -> Self {
+        Self {
+            bucket: Mutex::new(Bucket {
+                rate,
+                tokens: rate.unwrap_or(0) as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn rate(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.rate != rate {
+            *bucket = Bucket {
+                rate,
+                tokens: rate.unwrap_or(0) as f64,
+                updated: Instant::now(),
+            };
+        }
+    }
+
+    // 消耗n字节的令牌，不足时记为欠账，返回还清欠账需要等待的时间
+    // 共享同一个桶的会话各自等待，总速度不超过限制
+    pub fn consume(&self, n: usize) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let Some(rate) = bucket.rate else {
+            return Duration::ZERO;
+        };
+        let rate = rate as f64;
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - n as f64;
+        bucket.updated = now;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+// 一组下载和上传限速
+pub struct Rates {
+    pub download: RateLimiter,
+    pub upload: RateLimiter,
+}
+
+impl Rates {
+    fn new((download, upload): (Option<u64>, Option<u64>)) -> Self {
+        Self {
+            download: RateLimiter::new(download),
+            upload: RateLimiter::new(upload),
+        }
+    }
+
+    fn set(&self, (download, upload): (Option<u64>, Option<u64>)) {
+        self.download.set_rate(download);
+        self.upload.set_rate(upload);
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    per_ip: (Option<u64>, Option<u64>),
+    per_user: HashMap<String, (Option<u64>, Option<u64>)>,
+    ips: HashMap<IpAddr, Weak<Rates>>,
+    users: HashMap<String, Weak<Rates>>,
+}
+
+// 所有会话共享的限速，同一IP或同一用户的会话共用令牌桶
+pub struct Bandwidth {
+    global: Arc<Rates>,
+    shared: Mutex<Shared>,
+}
+
+impl Default for Bandwidth {
+    fn default() -> Self {
+        Self {
+            global: Arc::new(Rates::new((None, None))),
+            shared: Mutex::default(),
+        }
+    }
+}
+
+impl Bandwidth {
+    // 应用配置中的限速，重新加载配置后正在进行的传输也立即生效
+    pub fn configure(&self, config: &Config) {
+        self.global.set((config.download_rate, config.upload_rate));
+        let mut shared = self.shared.lock().unwrap();
+        shared.per_ip = (config.download_rate_per_ip, config.upload_rate_per_ip);
+        shared.per_user = config
+            .users
+            .iter()
+            .map(|(name, user)| (name.clone(), (user.download_rate, user.upload_rate)))
+            .collect();
+        for rates in shared.ips.values().filter_map(Weak::upgrade) {
+            rates.set(shared.per_ip);
+        }
+        for (name, rates) in &shared.users {
+            if let Some(rates) = rates.upgrade() {
+                rates.set(shared.per_user.get(name).copied().unwrap_or_default());
+            }
+        }
+    }
+
+    pub fn global(&self) -> Arc<Rates> {
+        self.global.clone()
+    }
+
+    pub fn for_ip(&self, ip: IpAddr) -> Arc<Rates> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.ips.retain(|_, rates| rates.strong_count() > 0);
+        if let Some(rates) = shared.ips.get(&ip).and_then(Weak::upgrade) {
+            return rates;
+        }
+        let rates = Arc::new(Rates::new(shared.per_ip));
+        shared.ips.insert(ip, Arc::downgrade(&rates));
+        rates
+    }
+
+    pub fn for_user(&self, name: &str) -> Arc<Rates> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.users.retain(|_, rates| rates.strong_count() > 0);
+        if let Some(rates) = shared.users.get(name).and_then(Weak::upgrade) {
+            return rates;
+        }
+        let limits = shared.per_user.get(name).copied().unwrap_or_default();
+        let rates = Arc::new(Rates::new(limits));
+        shared
+            .users
+            .insert(name.to_string(), Arc::downgrade(&rates));
+        rates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserConfig;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Some(1000));
+        // 初始允许一秒的突发流量
+        assert_eq!(limiter.consume(1000), Duration::ZERO);
+        let wait = limiter.consume(500);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+        // 欠账累积，后来者需要等待更久
+        assert!(limiter.consume(500) > Duration::from_millis(950));
+        limiter.set_rate(None);
+        assert_eq!(limiter.consume(1 << 20), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth() {
+        let bandwidth = Bandwidth::default();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let a = bandwidth.for_ip(ip);
+        assert!(Arc::ptr_eq(&a, &bandwidth.for_ip(ip)));
+        assert_eq!(a.download.rate(), None);
+
+        let mut config = Config {
+            download_rate_per_ip: Some(100),
+            ..Default::default()
+        };
+        config.users.insert(
+            "alice".into(),
+            UserConfig {
+                upload_rate: Some(50),
+                ..Default::default()
+            },
+        );
+        bandwidth.configure(&config);
+        assert_eq!(a.download.rate(), Some(100));
+        assert_eq!(bandwidth.for_user("alice").upload.rate(), Some(50));
+        assert_eq!(bandwidth.for_user("bob").upload.rate(), None);
+
+        // 没有会话使用时重新创建
+        drop(a);
+        config.download_rate_per_ip = None;
+        bandwidth.configure(&config);
+        assert_eq!(bandwidth.for_ip(ip).download.rate(), None);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{quota::Usage, storage::move_path};
+
+// 定期清除过期项的间隔
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// 回收站中的一项，id为回收站内的文件名
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    pub id: String,
+    pub path: PathBuf,
+    pub deleted: SystemTime,
+}
+
+// 用户的回收站，位于根目录之外
+// 每项保存为<id>，原路径和删除时间记录在<id>.info中
+#[derive(Debug, Clone)]
+pub struct Trash {
+    dir: PathBuf,
+    retention: Duration,
+}
+
+const INFO_EXT: &str = ".info";
+
+impl Trash {
+    // 每个用户使用单独的子目录，目录名总是用户名的十六进制编码，不同用户不会冲突
+    pub fn new(dir: &Path, username: &str, retention: Duration) -> Self {
+        Self {
+            dir: dir.join(hex::encode(username)),
+            retention,
+        }
+    }
+
+    // 将实际路径real移入回收站，path为客户端看到的原路径
+    pub async fn put(&self, real: PathBuf, path: PathBuf) -> io::Result<()> {
+        let trash = self.clone();
+        tokio::task::spawn_blocking(move || {
+            trash.purge_blocking();
+            std::fs::create_dir_all(&trash.dir)?;
+            let now = SystemTime::now();
+            let id = trash.write_info(&path, now)?;
+            let res = move_path(&real, &trash.dir.join(&id));
+            if res.is_err() {
+                let _ = std::fs::remove_file(trash.info_path(&id));
+            }
+            res
+        })
+        .await?
+    }
+
+    // 按删除时间从新到旧排列
+    pub async fn list(&self) -> io::Result<Vec<TrashEntry>> {
+        let trash = self.clone();
+        tokio::task::spawn_blocking(move || {
+            trash.purge_blocking();
+            trash.entries()
+        })
+        .await?
+    }
+
+    // 恢复到实际路径target，目标已存在时返回AlreadyExists
+    pub async fn restore(&self, id: &str, target: PathBuf) -> io::Result<()> {
+        let trash = self.clone();
+        let id = id.to_string();
+        tokio::task::spawn_blocking(move || {
+            if target.symlink_metadata().is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Target already exists",
+                ));
+            }
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            move_path(&trash.dir.join(&id), &target)?;
+            std::fs::remove_file(trash.info_path(&id))
+        })
+        .await?
+    }
+
+    // 清除超过保留时间的项
+    pub async fn purge(&self) {
+        let trash = self.clone();
+        let _ = tokio::task::spawn_blocking(move || trash.purge_blocking()).await;
+    }
+
+    // 各项按原路径的占用，用于计入用户的配额
+    pub async fn usage(&self) -> io::Result<Vec<(PathBuf, Usage)>> {
+        let trash = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut usage = Vec::new();
+            for entry in trash.entries()? {
+                usage.push((entry.path, disk_usage(&trash.dir.join(&entry.id))?));
+            }
+            Ok(usage)
+        })
+        .await?
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}{}", id, INFO_EXT))
+    }
+
+    // 以创建info文件的方式分配不重复的id
+    fn write_info(&self, path: &Path, deleted: SystemTime) -> io::Result<String> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let secs = deleted
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let info = format!("path={}\ndeleted={}\n", path.display(), secs);
+        loop {
+            let id = format!("{}-{}", secs, COUNTER.fetch_add(1, Ordering::Relaxed));
+            match std::fs::File::options()
+                .write(true)
+                .create_new(true)
+                .open(self.info_path(&id))
+            {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    file.write_all(info.as_bytes())?;
+                    return Ok(id);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn entries(&self) -> io::Result<Vec<TrashEntry>> {
+        let dir = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for entry in dir {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let Some(id) = name.strip_suffix(INFO_EXT) else {
+                continue;
+            };
+            let Ok(text) = std::fs::read_to_string(self.info_path(id)) else {
+                continue;
+            };
+            if let Some(entry) = parse_info(id, &text) {
+                entries.push(entry);
+            }
+        }
+        // id为"<秒>-<序号>"，同一秒内按序号排列
+        let order = |id: &str| -> (u64, u64) {
+            let (secs, n) = id.split_once('-').unwrap_or((id, "0"));
+            (secs.parse().unwrap_or(0), n.parse().unwrap_or(0))
+        };
+        entries.sort_by_key(|entry| std::cmp::Reverse((entry.deleted, order(&entry.id))));
+        Ok(entries)
+    }
+
+    fn purge_blocking(&self) {
+        let Ok(entries) = self.entries() else {
+            return;
+        };
+        let now = SystemTime::now();
+        for entry in entries {
+            if entry.deleted + self.retention > now {
+                continue;
+            }
+            let item = self.dir.join(&entry.id);
+            let res = match item.symlink_metadata() {
+                Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&item),
+                Ok(_) => std::fs::remove_file(&item),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e),
+            };
+            match res.and_then(|_| std::fs::remove_file(self.info_path(&entry.id))) {
+                Ok(()) => log::info!("Purged {} from trash", entry.path.display()),
+                Err(e) => log::warn!("Failed to purge {} from trash: {}", item.display(), e),
+            }
+        }
+    }
+}
+
+// 清除dir下所有用户回收站中过期的项，由服务器定期调用，不依赖用户登录
+pub async fn purge_all(dir: &Path, retention: Duration) {
+    let dir = dir.to_path_buf();
+    let _ = tokio::task::spawn_blocking(move || {
+        let Ok(users) = std::fs::read_dir(&dir) else {
+            return;
+        };
+        for user in users.flatten() {
+            if user.file_type().is_ok_and(|t| t.is_dir()) {
+                Trash {
+                    dir: user.path(),
+                    retention,
+                }
+                .purge_blocking();
+            }
+        }
+    })
+    .await;
+}
+
+// 回收站中一项的占用，不跟随符号链接
+fn disk_usage(path: &Path) -> io::Result<Usage> {
+    let metadata = path.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return Ok(Usage::file(metadata.is_file().then_some(metadata.len())));
+    }
+    let mut total = Usage::default();
+    for entry in std::fs::read_dir(path)? {
+        let usage = disk_usage(&entry?.path())?;
+        total.bytes += usage.bytes;
+        total.files += usage.files;
+    }
+    Ok(total)
+}
+
+fn parse_info(id: &str, text: &str) -> Option<TrashEntry> {
+    let mut path = None;
+    let mut deleted = None;
+    for line in text.lines() {
+        match line.split_once('=') {
+            Some(("path", value)) => path = Some(PathBuf::from(value)),
+            Some(("deleted", value)) => {
+                deleted = Some(UNIX_EPOCH + Duration::from_secs(value.parse().ok()?))
+            }
+            _ => {}
+        }
+    }
+    Some(TrashEntry {
+        id: id.to_string(),
+        path: path?,
+        deleted: deleted?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trash() {
+        let root = std::env::temp_dir().join(format!("ftpserver-trash-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("files/dir")).unwrap();
+        std::fs::write(root.join("files/dir/a.txt"), b"a").unwrap();
+        std::fs::write(root.join("files/b.txt"), b"b").unwrap();
+        let trash = Trash::new(&root.join("trash"), "alice", Duration::from_secs(3600));
+
+        trash
+            .put(root.join("files/dir"), PathBuf::from("/dir"))
+            .await
+            .unwrap();
+        trash
+            .put(root.join("files/b.txt"), PathBuf::from("/b.txt"))
+            .await
+            .unwrap();
+        assert!(!root.join("files/dir").exists());
+        let entries = trash.list().await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, Path::new("/b.txt"));
+        assert_eq!(
+            trash.usage().await.unwrap(),
+            [
+                (PathBuf::from("/b.txt"), Usage::file(Some(1))),
+                (PathBuf::from("/dir"), Usage::file(Some(1)))
+            ]
+        );
+
+        // 目标已存在时不覆盖
+        std::fs::write(root.join("files/b.txt"), b"new").unwrap();
+        let err = trash
+            .restore(&entries[0].id, root.join("files/b.txt"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        trash
+            .restore(&entries[1].id, root.join("files/dir"))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("files/dir/a.txt")).unwrap(), b"a");
+        assert_eq!(trash.list().await.unwrap().len(), 1);
+
+        // 保留时间为0时立即清除，不需要用户登录
+        purge_all(&root.join("trash"), Duration::ZERO).await;
+        assert_eq!(
+            std::fs::read_dir(root.join("trash/616c696365"))
+                .unwrap()
+                .count(),
+            0
+        );
+
+        // 用户名总是编码，"2e2e2f78"和"../x"不会共用回收站
+        assert_eq!(
+            Trash::new(Path::new("/t"), "../x", Duration::ZERO).dir,
+            Path::new("/t/2e2e2f78")
+        );
+        assert_ne!(
+            Trash::new(Path::new("/t"), "2e2e2f78", Duration::ZERO).dir,
+            Path::new("/t/2e2e2f78")
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,653 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use ftpserver::{
+    Authenticator, Config, Event, EventHooks, MemoryFs, Server, SessionInfo, StorageBackend,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, tcp::OwnedWriteHalf},
+};
+
+struct Client {
+    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(addr: std::net::SocketAddr) -> Self {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    async fn reply(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).await.unwrap();
+        line.trim_end().to_string()
+    }
+
+    async fn cmd(&mut self, cmd: &str) -> String {
+        self.writer
+            .write_all(format!("{}\r\n", cmd).as_bytes())
+            .await
+            .unwrap();
+        self.reply().await
+    }
+
+    async fn login(addr: std::net::SocketAddr, name: &str) -> Self {
+        let mut client = Self::connect(addr).await;
+        client.reply().await;
+        client.cmd(&format!("USER {}", name)).await;
+        assert!(client.cmd("PASS x").await.starts_with("230"));
+        client
+    }
+
+    // 通过被动模式上传，返回传输结束的回复
+    async fn stor(&mut self, name: &str, data: &[u8]) -> String {
+        let mut conn = self.pasv().await;
+        assert!(self.cmd(&format!("STOR {}", name)).await.starts_with("150"));
+        let _ = conn.write_all(data).await;
+        drop(conn);
+        self.reply().await
+    }
+
+    // 进入被动模式并连接数据端口
+    async fn pasv(&mut self) -> TcpStream {
+        let reply = self.cmd("PASV").await;
+        assert!(reply.starts_with("227"), "{}", reply);
+        let start = reply.find('(').unwrap() + 1;
+        let end = reply.find(')').unwrap();
+        let n: Vec<u16> = reply[start..end]
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect();
+        TcpStream::connect(("127.0.0.1", (n[4] << 8) | n[5]))
+            .await
+            .unwrap()
+    }
+}
+
+struct OnlyBob;
+
+#[async_trait]
+impl Authenticator for OnlyBob {
+    async fn authenticate(&self, username: &str, password: &str) -> io::Result<bool> {
+        Ok(username == "bob" && password == "hunter2")
+    }
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+impl EventHooks for Recorder {
+    fn on_event(&self, _session: &SessionInfo, event: &Event) {
+        self.0.lock().unwrap().push(event.clone());
+    }
+}
+
+fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("ftpserver-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+#[tokio::test]
+async fn test_embedded_server() {
+    let root = temp_root("embedded");
+    let config = Config {
+        root: root.clone(),
+        banner: "embedded".into(),
+        ..Default::default()
+    };
+    let recorder = Recorder::default();
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .authenticator(OnlyBob)
+        .hooks(recorder.clone())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+    let addr = handle.local_addrs()[0];
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(client.reply().await, "220 embedded");
+    client.cmd("USER alice").await;
+    assert!(client.cmd("PASS hunter2").await.starts_with("530"));
+    client.cmd("USER bob").await;
+    assert!(client.cmd("PASS hunter2").await.starts_with("230"));
+    assert!(client.cmd("MKD sub").await.starts_with("257"));
+    assert!(root.join("sub").is_dir());
+
+    handle.shutdown();
+    assert!(client.reply().await.starts_with("421"));
+    handle.await.unwrap();
+
+    let events = recorder.0.lock().unwrap().clone();
+    assert_eq!(
+        events,
+        vec![
+            Event::Connected,
+            Event::LoginFailed {
+                username: "alice".into()
+            },
+            Event::LoggedIn,
+            Event::DirectoryCreated {
+                path: PathBuf::from("/sub")
+            },
+            Event::Disconnected,
+        ]
+    );
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_memory_storage() {
+    let storage = MemoryFs::new();
+    let config = Config {
+        root: PathBuf::from("/nonexistent"),
+        ..Default::default()
+    };
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER anonymous").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    assert!(client.cmd("MKD fw").await.starts_with("257"));
+    assert!(client.cmd("CWD fw").await.starts_with("250"));
+
+    let mut data = client.pasv().await;
+    assert!(client.cmd("STOR image.bin").await.starts_with("150"));
+    data.write_all(b"firmware").await.unwrap();
+    drop(data);
+    assert!(client.reply().await.starts_with("226"));
+    let meta = storage.metadata("/fw/image.bin".as_ref()).await.unwrap();
+    assert_eq!(meta.len, 8);
+
+    let mut data = client.pasv().await;
+    assert!(client.cmd("RETR /fw/image.bin").await.starts_with("150"));
+    let mut buf = Vec::new();
+    data.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"firmware");
+    assert!(client.reply().await.starts_with("226"));
+
+    let mut data = client.pasv().await;
+    assert!(client.cmd("NLST").await.starts_with("150"));
+    let mut names = String::new();
+    data.read_to_string(&mut names).await.unwrap();
+    assert_eq!(names, "image.bin\n");
+    assert!(client.reply().await.starts_with("226"));
+
+    assert!(client.cmd("RETR missing.bin").await.starts_with("550"));
+    assert!(client.cmd("QUIT").await.starts_with("221"));
+    handle.shutdown();
+    handle.await.unwrap();
+}
+
+// SITE QUOTA只有一个配额时的输出行
+async fn site_quota(client: &mut Client) -> String {
+    assert_eq!(client.cmd("SITE QUOTA").await, "211-Quota usage:");
+    let line = client.reply().await;
+    assert_eq!(client.reply().await, "211 End");
+    line
+}
+
+#[tokio::test]
+async fn test_quota() {
+    let users = temp_root("quota").join("users.toml");
+    std::fs::write(
+        &users,
+        "[users.alice]\npassword = \"x\"\nhome = \"/alice\"\nquota = { bytes = 10 }\n\
+         [users.carol]\npassword = \"x\"\nhome = \"/carol\"\nquota = { bytes = 10 }\n\
+         [users.bob]\nanonymous = true\n",
+    )
+    .unwrap();
+    let config = Config {
+        users_file: Some(users),
+        ..Default::default()
+    };
+    let storage = MemoryFs::new();
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+    let addr = handle.local_addrs()[0];
+    // 登录时创建主目录，客户端看到的根目录是主目录
+    let mut alice = Client::login(addr, "alice").await;
+    assert_eq!(alice.cmd("PWD").await, "257 /");
+    assert!(alice.stor("a.bin", b"123456").await.starts_with("226"));
+    assert!(storage.metadata("/alice/a.bin".as_ref()).await.is_ok());
+
+    // 超出配额的部分被拒绝，传输以552中止，不会留下文件
+    assert!(alice.stor("b.bin", b"12345678").await.starts_with("552"));
+    assert_eq!(
+        site_quota(&mut alice).await,
+        " /: 6 of 10 bytes, 1 of unlimited files"
+    );
+    assert!(alice.cmd("DELE b.bin").await.starts_with("550"));
+    assert!(alice.cmd("CWD ../carol").await.starts_with("550"));
+
+    // 每个用户只统计自己主目录下的文件
+    let mut carol = Client::login(addr, "carol").await;
+    assert_eq!(
+        site_quota(&mut carol).await,
+        " /: 0 of 10 bytes, 0 of unlimited files"
+    );
+    assert!(carol.stor("c.bin", b"12345678").await.starts_with("226"));
+    assert_eq!(
+        site_quota(&mut alice).await,
+        " /: 6 of 10 bytes, 1 of unlimited files"
+    );
+
+    // 没有主目录的用户写入alice的主目录时计入alice的配额，同一用户的会话看到相同的使用情况
+    let mut bob = Client::login(addr, "bob").await;
+    assert!(bob.stor("/alice/x.bin", b"12").await.starts_with("226"));
+    let mut second = Client::login(addr, "alice").await;
+    assert_eq!(
+        site_quota(&mut second).await,
+        " /: 8 of 10 bytes, 2 of unlimited files"
+    );
+    assert!(bob.cmd("DELE /alice/x.bin").await.starts_with("250"));
+
+    // 覆盖已有文件时可以使用原文件的空间
+    assert!(alice.stor("a.bin", b"0123456789").await.starts_with("226"));
+    assert!(alice.cmd("STOR c.bin").await.starts_with("452"));
+
+    // 该用户的会话都结束后，下次登录重新统计
+    assert!(alice.cmd("QUIT").await.starts_with("221"));
+    assert!(second.cmd("QUIT").await.starts_with("221"));
+    storage.remove_file("/alice/a.bin".as_ref()).await.unwrap();
+    let mut alice = Client::login(addr, "alice").await;
+    assert_eq!(
+        site_quota(&mut alice).await,
+        " /: 0 of 10 bytes, 0 of unlimited files"
+    );
+
+    handle.shutdown();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_site_copy() {
+    let users = temp_root("copy").join("users.toml");
+    std::fs::write(
+        &users,
+        "[users.alice]\npassword = \"x\"\nhome = \"/alice\"\nquota = { bytes = 10 }\nsite_commands = [\"CPFR\", \"CPTO\"]\n",
+    )
+    .unwrap();
+    let config = Config {
+        users_file: Some(users),
+        ..Default::default()
+    };
+    let storage = MemoryFs::new();
+    storage.mkdir("/alice".as_ref()).await.unwrap();
+    for (path, data) in [("/alice/a.bin", &b"123456"[..]), ("/alice/b.bin", b"12")] {
+        let mut w = storage.open_write(path.as_ref(), false).await.unwrap();
+        w.write_all(data).await.unwrap();
+        w.shutdown().await.unwrap();
+    }
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER alice").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+
+    assert!(client.cmd("SITE CPTO c.bin").await.starts_with("503"));
+    assert!(client.cmd("SITE CPFR b.bin").await.starts_with("350"));
+    assert!(client.cmd("SITE CPTO c.bin").await.starts_with("250"));
+    let meta = storage.metadata("/alice/c.bin".as_ref()).await.unwrap();
+    assert_eq!(meta.len, 2);
+
+    // 已存在的目标不会被覆盖
+    assert!(client.cmd("SITE CPFR b.bin").await.starts_with("350"));
+    assert!(client.cmd("SITE CPTO a.bin").await.starts_with("553"));
+    let meta = storage.metadata("/alice/a.bin".as_ref()).await.unwrap();
+    assert_eq!(meta.len, 6);
+
+    // 复制的文件计入配额，超出时拒绝
+    assert!(client.cmd("SITE CPFR a.bin").await.starts_with("350"));
+    assert!(client.cmd("SITE CPTO d.bin").await.starts_with("452"));
+    assert!(storage.metadata("/alice/d.bin".as_ref()).await.is_err());
+    assert!(client.cmd("SITE CPTO d.bin").await.starts_with("503"));
+
+    assert!(client.cmd("QUIT").await.starts_with("221"));
+    handle.shutdown();
+    handle.await.unwrap();
+}
+
+async fn assert_modified(storage: &MemoryFs, secs: u64) {
+    let meta = storage.metadata("/a.txt".as_ref()).await.unwrap();
+    assert_eq!(
+        meta.modified,
+        Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+    );
+}
+
+#[tokio::test]
+async fn test_set_mtime() {
+    let users = temp_root("mtime").join("users.toml");
+    std::fs::write(
+        &users,
+        "[users.alice]\npassword = \"x\"\nsite_commands = [\"UTIME\"]\n\n[users.bob]\nanonymous = true\n",
+    )
+    .unwrap();
+    let config = Config {
+        users_file: Some(users),
+        ..Default::default()
+    };
+    let storage = MemoryFs::new();
+    let mut w = storage.open_write("/a.txt".as_ref(), false).await.unwrap();
+    w.write_all(b"a").await.unwrap();
+    w.shutdown().await.unwrap();
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER alice").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    assert_eq!(
+        client.cmd("MFMT 20200102030405 a.txt").await,
+        "213 Modify=20200102030405; a.txt"
+    );
+    assert_modified(&storage, 1577934245).await;
+    assert_eq!(
+        client.cmd("MFF modify=20210102030405; a.txt").await,
+        "213 Modify=20210102030405; a.txt"
+    );
+    assert_modified(&storage, 1609556645).await;
+    assert_eq!(
+        client.cmd("SITE UTIME 202201020304 a.txt").await,
+        "213 Modify=20220102030400; a.txt"
+    );
+    assert_modified(&storage, 1641092640).await;
+
+    // 无效的时间不会修改文件
+    assert!(client.cmd("MFMT 2020010203 a.txt").await.starts_with("501"));
+    assert!(client.cmd("MFF modify=x; a.txt").await.starts_with("501"));
+    assert!(
+        client
+            .cmd("SITE UTIME 20221302 a.txt")
+            .await
+            .starts_with("501")
+    );
+    assert_modified(&storage, 1641092640).await;
+    assert!(client.cmd("QUIT").await.starts_with("221"));
+
+    // 未获得UTIME权限的用户三条命令都被拒绝
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER bob").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    assert!(
+        client
+            .cmd("MFMT 20200102030405 a.txt")
+            .await
+            .starts_with("550")
+    );
+    assert!(
+        client
+            .cmd("MFF modify=20200102030405; a.txt")
+            .await
+            .starts_with("550")
+    );
+    assert!(
+        client
+            .cmd("SITE UTIME 202001020304 a.txt")
+            .await
+            .starts_with("550")
+    );
+    assert_modified(&storage, 1641092640).await;
+    assert!(client.cmd("QUIT").await.starts_with("221"));
+
+    handle.shutdown();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rename() {
+    let storage = MemoryFs::new();
+    let mut w = storage.open_write("/a.txt".as_ref(), false).await.unwrap();
+    w.write_all(b"a").await.unwrap();
+    w.shutdown().await.unwrap();
+    let server = Server::builder(Config::default())
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER anonymous").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    assert!(client.cmd("RNFR a.txt").await.starts_with("350"));
+    assert!(client.cmd("RNTO b.txt").await.starts_with("250"));
+    assert!(storage.metadata("/b.txt".as_ref()).await.is_ok());
+
+    // RNFR只对紧接着的RNTO有效
+    assert!(client.cmd("RNFR b.txt").await.starts_with("350"));
+    assert!(client.cmd("NOOP").await.starts_with("200"));
+    assert!(client.cmd("RNTO c.txt").await.starts_with("503"));
+    assert!(storage.metadata("/b.txt".as_ref()).await.is_ok());
+    assert!(storage.metadata("/c.txt".as_ref()).await.is_err());
+
+    assert!(client.cmd("QUIT").await.starts_with("221"));
+    handle.shutdown();
+    handle.await.unwrap();
+}
+
+// 服务器发送421后应关闭控制连接
+async fn assert_closed_with_421(client: &mut Client) {
+    let reply = client.reply().await;
+    assert!(reply.starts_with("421"), "{}", reply);
+    let mut rest = String::new();
+    let n = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        client.reader.read_line(&mut rest),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(n, 0, "{}", rest);
+}
+
+#[tokio::test]
+async fn test_session_timeouts() {
+    let config = Config {
+        idle_timeout: std::time::Duration::from_millis(300),
+        ..Default::default()
+    };
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(MemoryFs::new())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+
+    // 空闲超时
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER anonymous").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    let started = std::time::Instant::now();
+    assert_closed_with_421(&mut client).await;
+    assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+    handle.shutdown();
+    handle.await.unwrap();
+
+    // 持续发送命令也不能超过最长会话时间
+    let config = Config {
+        max_session: Some(std::time::Duration::from_secs(1)),
+        ..Default::default()
+    };
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(MemoryFs::new())
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    let started = std::time::Instant::now();
+    client.reply().await;
+    client.cmd("USER anonymous").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    while started.elapsed() < std::time::Duration::from_millis(500) {
+        assert!(client.cmd("NOOP").await.starts_with("200"));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_closed_with_421(&mut client).await;
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+    handle.shutdown();
+    handle.await.unwrap();
+}
+
+// 启动一个客户端暂不读取的下载，然后关闭服务器
+async fn start_slow_retr(
+    drain: std::time::Duration,
+) -> (ftpserver::ServerHandle, Client, TcpStream) {
+    let storage = MemoryFs::new();
+    let mut w = storage
+        .open_write("/big.bin".as_ref(), false)
+        .await
+        .unwrap();
+    w.write_all(&vec![7; 32 << 20]).await.unwrap();
+    w.shutdown().await.unwrap();
+    let config = Config {
+        drain_timeout: drain,
+        ..Default::default()
+    };
+    let server = Server::builder(config)
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(storage)
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+    let mut client = Client::connect(handle.local_addrs()[0]).await;
+    client.reply().await;
+    client.cmd("USER anonymous").await;
+    assert!(client.cmd("PASS x").await.starts_with("230"));
+    let data = client.pasv().await;
+    assert!(client.cmd("RETR big.bin").await.starts_with("150"));
+    handle.shutdown();
+    (handle, client, data)
+}
+
+#[tokio::test]
+async fn test_shutdown_drain() {
+    let timeout = |d| tokio::time::timeout(std::time::Duration::from_secs(10), d);
+
+    // 传输在drain_timeout内完成
+    let (handle, mut client, mut data) = start_slow_retr(std::time::Duration::from_secs(30)).await;
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let mut buf = Vec::new();
+    data.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf.len(), 32 << 20);
+    assert!(client.reply().await.starts_with("226"));
+    assert_closed_with_421(&mut client).await;
+    timeout(handle).await.unwrap().unwrap();
+
+    // 超过drain_timeout的传输被中止
+    let (handle, mut client, data) = start_slow_retr(std::time::Duration::from_millis(200)).await;
+    let started = std::time::Instant::now();
+    let reply = client.reply().await;
+    assert!(reply.starts_with("426"), "{}", reply);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+    assert_closed_with_421(&mut client).await;
+    timeout(handle).await.unwrap().unwrap();
+    drop(data);
+}
+
+#[tokio::test]
+async fn test_reload() {
+    let users = temp_root("reload").join("users.toml");
+    std::fs::write(
+        &users,
+        "[users.alice]\npassword = \"x\"\n\n[users.bob]\nanonymous = true\n",
+    )
+    .unwrap();
+    let banner = Arc::new(Mutex::new(String::from("before")));
+    let config = {
+        let (users, banner) = (users.clone(), banner.clone());
+        move || {
+            let mut config = Config {
+                users_file: Some(users.clone()),
+                banner: banner.lock().unwrap().clone(),
+                ..Default::default()
+            };
+            config.validate()?;
+            Ok(config)
+        }
+    };
+    let server = Server::builder(config().unwrap())
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .storage(MemoryFs::new())
+        .on_reload(config)
+        .build()
+        .await
+        .unwrap();
+    let handle = server.start().unwrap();
+    let addr = handle.local_addrs()[0];
+    let mut alice = Client::connect(addr).await;
+    assert_eq!(alice.reply().await, "220 before");
+    alice.cmd("USER alice").await;
+    assert!(alice.cmd("PASS x").await.starts_with("230"));
+
+    // 无关的配置变化不影响已有会话，新会话使用新配置
+    *banner.lock().unwrap() = "after".into();
+    handle.reload();
+    loop {
+        let mut client = Client::connect(addr).await;
+        if client.reply().await == "220 after" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(alice.cmd("NOOP").await.starts_with("200"));
+
+    // 删除用户后其会话被关闭
+    std::fs::write(&users, "[users.bob]\nanonymous = true\n").unwrap();
+    handle.reload();
+    assert_closed_with_421(&mut alice).await;
+    let mut client = Client::connect(addr).await;
+    client.reply().await;
+    client.cmd("USER alice").await;
+    assert!(client.cmd("PASS x").await.starts_with("530"));
+    assert!(client.cmd("QUIT").await.starts_with("221"));
+
+    handle.shutdown();
+    handle.await.unwrap();
+}